@@ -0,0 +1,161 @@
+//! Runs every program in `benches/lox` through `rux run --bench` and prints wall
+//! time, instructions executed and allocations for each of them.
+//!
+//! Results of the previous run are kept in `target/lox-bench.txt` so that the
+//! next run can show how much each program changed. Programs that got slower
+//! by more than `REGRESSION` percent are flagged.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const RUNS: usize = 5;
+
+/// Slowdown against the baseline, in percent, that counts as a regression
+/// rather than noise.
+const REGRESSION: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Measurement {
+    time: f64,
+    instructions: u64,
+    allocations: u64,
+}
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let baseline_path = root.join("target").join("lox-bench.txt");
+    let baseline = read_baseline(&baseline_path);
+
+    let mut programs: Vec<PathBuf> = fs::read_dir(root.join("benches").join("lox"))
+        .expect("Could not read the benchmark directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    programs.sort();
+
+    println!(
+        "{:<16} {:>12} {:>10} {:>14} {:>12}",
+        "benchmark", "time (ms)", "change", "instructions", "allocations"
+    );
+
+    let mut results = vec![];
+    let mut regressions = vec![];
+    for program in programs {
+        let name = program.file_stem().unwrap().to_string_lossy().to_string();
+        match measure(&program) {
+            Ok(measurement) => {
+                let change = baseline
+                    .get(&name)
+                    .map(|previous| (measurement.time - previous.time) / previous.time * 100.0);
+                let regressed = change.is_some_and(|change| change > REGRESSION);
+                println!(
+                    "{:<16} {:>12.3} {:>10} {:>14} {:>12}{}",
+                    name,
+                    measurement.time * 1000.0,
+                    change.map_or(String::from("-"), |change| format!("{change:+.1}%")),
+                    measurement.instructions,
+                    measurement.allocations,
+                    if regressed { "  REGRESSION" } else { "" }
+                );
+                if regressed {
+                    regressions.push(name.clone());
+                }
+                results.push((name, measurement));
+            }
+            Err(reason) => println!("{:<16} failed: {}", name, reason),
+        }
+    }
+
+    write_baseline(&baseline_path, &results);
+    if !regressions.is_empty() {
+        println!(
+            "\nSlower by more than {REGRESSION}%: {}",
+            regressions.join(", ")
+        );
+    }
+}
+
+/// Runs the program `RUNS` times and keeps the fastest run.
+fn measure(program: &Path) -> Result<Measurement, String> {
+    let mut best: Option<Measurement> = None;
+    for _ in 0..RUNS {
+        let output = Command::new(env!("CARGO_BIN_EXE_Rux"))
+            .arg("run")
+            .arg("--bench")
+            .arg(program)
+            .output()
+            .map_err(|error| error.to_string())?;
+        let report = String::from_utf8_lossy(&output.stderr);
+
+        if !output.status.success() {
+            let reason = report
+                .lines()
+                .map(str::trim)
+                .find(|line| {
+                    !line.is_empty() && !line.starts_with("thread '") && !line.starts_with("note:")
+                })
+                .unwrap_or("no output");
+            return Err(format!("{} ({})", reason, output.status));
+        }
+
+        let measurement = parse_report(&report)?;
+        if best.is_none_or(|best| measurement.time < best.time) {
+            best = Some(measurement);
+        }
+    }
+    Ok(best.unwrap())
+}
+
+fn parse_report(report: &str) -> Result<Measurement, String> {
+    let fields: HashMap<&str, &str> = report
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .collect();
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .ok_or(format!("Missing '{name}' in report"))
+            .map(|value| value.trim())
+    };
+
+    Ok(Measurement {
+        time: field("time")?.parse().map_err(|_| "Malformed time")?,
+        instructions: field("instructions")?
+            .parse()
+            .map_err(|_| "Malformed instruction count")?,
+        allocations: field("allocations")?
+            .parse()
+            .map_err(|_| "Malformed allocation count")?,
+    })
+}
+
+fn read_baseline(path: &Path) -> HashMap<String, Measurement> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?.to_string();
+            let measurement = Measurement {
+                time: parts.next()?.parse().ok()?,
+                instructions: parts.next()?.parse().ok()?,
+                allocations: parts.next()?.parse().ok()?,
+            };
+            Some((name, measurement))
+        })
+        .collect()
+}
+
+fn write_baseline(path: &Path, results: &[(String, Measurement)]) {
+    let contents: String = results
+        .iter()
+        .map(|(name, m)| format!("{} {} {} {}\n", name, m.time, m.instructions, m.allocations))
+        .collect();
+    if let Err(error) = fs::write(path, contents) {
+        eprintln!("Could not save results to {}: {}", path.display(), error);
+    }
+}
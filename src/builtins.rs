@@ -0,0 +1,232 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use crate::{
+    objects::{ListObject, MapObject, StringObject},
+    value::Value,
+    vm::{InterpretResult, RuntimeError},
+};
+
+/// `target[index]`.
+pub fn get_index(target: &Value, index: &Value) -> InterpretResult<Value> {
+    match target {
+        Value::List(list) => {
+            let list = list.borrow();
+            let position = list.position(index)?;
+            Ok(list.elements[position].clone())
+        }
+        Value::Map(map) => map
+            .borrow()
+            .get(index)?
+            .cloned()
+            .ok_or_else(|| undefined_key(index)),
+        v => Err(not_indexable(v)),
+    }
+}
+
+/// `target[index] = value`.
+pub fn set_index(target: &Value, index: &Value, value: Value) -> InterpretResult<()> {
+    match target {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let position = list.position(index)?;
+            list.elements[position] = value;
+            Ok(())
+        }
+        Value::Map(map) => map.borrow_mut().insert(index.clone(), value),
+        v => Err(not_indexable(v)),
+    }
+}
+
+fn undefined_key(key: &Value) -> RuntimeError {
+    RuntimeError::new(&format!("Undefined key '{key}'."))
+}
+
+fn not_indexable(value: &Value) -> RuntimeError {
+    RuntimeError::new(&format!(
+        "Can't index into {} '{value}'.",
+        value.type_name()
+    ))
+}
+
+/// The element of `iterable` at `cursor` and the cursor of the one after it,
+/// or `None` once there are no more. Lists give their elements, maps their
+/// keys and strings their characters.
+pub fn iterate(iterable: &Value, cursor: usize) -> InterpretResult<Option<(Value, usize)>> {
+    let next = match iterable {
+        Value::List(list) => list.borrow().elements.get(cursor).cloned(),
+        Value::Map(map) => map.borrow().entries.get(cursor).map(|(key, _)| key.clone()),
+        Value::String(string) => {
+            let character = string
+                .value
+                .get(cursor..)
+                .and_then(|rest| rest.chars().next());
+            return Ok(character.map(|character| {
+                let element = StringObject::from_owned(character.to_string());
+                (
+                    Value::String(Rc::new(element)),
+                    cursor + character.len_utf8(),
+                )
+            }));
+        }
+        v => {
+            return Err(RuntimeError::new(&format!(
+                "Can't iterate over {} '{v}'.",
+                v.type_name()
+            )))
+        }
+    };
+    Ok(next.map(|element| (element, cursor + 1)))
+}
+
+/// Calls the built-in method `name` on `receiver`.
+pub fn invoke(receiver: &Value, name: &str, args: Vec<Value>) -> InterpretResult<Value> {
+    match receiver {
+        Value::List(list) => list_method(list, name, &args),
+        Value::Map(map) => map_method(map, name, &args),
+        v => Err(undefined_method(v, name)),
+    }
+}
+
+fn undefined_method(receiver: &Value, name: &str) -> RuntimeError {
+    RuntimeError::new(&format!(
+        "Undefined method '{name}' on {}.",
+        receiver.type_name()
+    ))
+}
+
+fn arity_error(name: &str, expected: &str, args: &[Value]) -> RuntimeError {
+    RuntimeError::new(&format!(
+        "'{name}' takes {expected} but got {}.",
+        args.len()
+    ))
+}
+
+/// Methods that only change the list return it, so calls can be chained.
+fn list_method(
+    list: &Rc<RefCell<ListObject>>,
+    name: &str,
+    args: &[Value],
+) -> InterpretResult<Value> {
+    let receiver = || Value::List(list.clone());
+    let mut elements = list.borrow_mut();
+    match name {
+        "push" => {
+            let [value] = args else {
+                return Err(arity_error(name, "1 argument", args));
+            };
+            elements.elements.push(value.clone());
+            drop(elements);
+            Ok(receiver())
+        }
+        "pop" => {
+            let [] = args else {
+                return Err(arity_error(name, "no arguments", args));
+            };
+            elements
+                .elements
+                .pop()
+                .ok_or_else(|| RuntimeError::new("Can't pop from an empty list."))
+        }
+        "len" => {
+            let [] = args else {
+                return Err(arity_error(name, "no arguments", args));
+            };
+            Ok(Value::Integer(elements.elements.len() as i64))
+        }
+        "insert" => {
+            let [index, value] = args else {
+                return Err(arity_error(name, "2 arguments", args));
+            };
+            let position = elements.insertion_point(index)?;
+            elements.elements.insert(position, value.clone());
+            drop(elements);
+            Ok(receiver())
+        }
+        "remove" => {
+            let [index] = args else {
+                return Err(arity_error(name, "1 argument", args));
+            };
+            let position = elements.position(index)?;
+            Ok(elements.elements.remove(position))
+        }
+        "slice" => {
+            let (start, end) = match args {
+                [start] => (elements.clamped(start)?, elements.elements.len()),
+                [start, end] => (elements.clamped(start)?, elements.clamped(end)?),
+                _ => return Err(arity_error(name, "1 or 2 arguments", args)),
+            };
+            let slice = elements.elements[start..end.max(start)].to_vec();
+            Ok(Value::List(Rc::new(RefCell::new(ListObject::new(slice)))))
+        }
+        "sort" => {
+            let [] = args else {
+                return Err(arity_error(name, "no arguments", args));
+            };
+            sort(&mut elements.elements)?;
+            drop(elements);
+            Ok(receiver())
+        }
+        _ => Err(undefined_method(&receiver(), name)),
+    }
+}
+
+fn map_method(map: &Rc<RefCell<MapObject>>, name: &str, args: &[Value]) -> InterpretResult<Value> {
+    let list = |values: Vec<Value>| Value::List(Rc::new(RefCell::new(ListObject::new(values))));
+    let mut map_object = map.borrow_mut();
+    match name {
+        "keys" => {
+            let [] = args else {
+                return Err(arity_error(name, "no arguments", args));
+            };
+            let keys = map_object.entries.iter().map(|(key, _)| key.clone());
+            Ok(list(keys.collect()))
+        }
+        "values" => {
+            let [] = args else {
+                return Err(arity_error(name, "no arguments", args));
+            };
+            let values = map_object.entries.iter().map(|(_, value)| value.clone());
+            Ok(list(values.collect()))
+        }
+        "has" => {
+            let [key] = args else {
+                return Err(arity_error(name, "1 argument", args));
+            };
+            Ok(Value::Boolean(map_object.get(key)?.is_some()))
+        }
+        "remove" => {
+            let [key] = args else {
+                return Err(arity_error(name, "1 argument", args));
+            };
+            map_object.remove(key)?.ok_or_else(|| undefined_key(key))
+        }
+        "len" => {
+            let [] = args else {
+                return Err(arity_error(name, "no arguments", args));
+            };
+            Ok(Value::Integer(map_object.len() as i64))
+        }
+        _ => Err(undefined_method(&Value::Map(map.clone()), name)),
+    }
+}
+
+/// Sorts numbers by value and strings by their bytes. Anything else, or a
+/// mix of the two, can't be sorted.
+fn sort(elements: &mut [Value]) -> InterpretResult<()> {
+    if elements.iter().all(|e| e.as_float().is_some()) {
+        elements.sort_by(|a, b| match (a, b) {
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (a, b) => a.as_float().unwrap().total_cmp(&b.as_float().unwrap()),
+        });
+    } else if elements.iter().all(|e| matches!(e, Value::String(_))) {
+        elements.sort_by(|a, b| match (a, b) {
+            (Value::String(a), Value::String(b)) => a.value.cmp(&b.value),
+            _ => Ordering::Equal,
+        });
+    } else {
+        return Err(RuntimeError::new(
+            "Only lists of numbers or of strings can be sorted.",
+        ));
+    }
+    Ok(())
+}
@@ -12,7 +12,76 @@ pub enum OpCode {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Not,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    /// Pops a value and the bounds of a range above it, and pushes whether
+    /// the value is a number in the range, including its end if set.
+    InRange(bool),
+    /// Turns the value on top of the stack into a string.
+    Stringify,
+
+    /// Makes a list of the given number of values on top of the stack.
+    BuildList(usize),
+    /// Makes a map of the given number of key and value pairs on top of the
+    /// stack.
+    BuildMap(usize),
+    Index,
+    SetIndex,
+    /// Pushes copies of the top two values, for `list[i] += 1`.
+    DuplicatePair,
+    /// Calls the built-in method named by the constant on the receiver
+    /// below the given number of arguments.
+    Invoke(usize, usize),
+
+    Pop,
+    /// Global variables, named by the constant.
+    DefineGlobal(usize),
+    GetGlobal(usize),
+    SetGlobal(usize),
+    /// Local variables, by their slot on the stack.
+    GetLocal(usize),
+    SetLocal(usize),
+    /// Pushes the module at the path in the constant.
+    Import(usize),
+    /// Replaces the module on top of the stack with what it defines under
+    /// the name in the constant.
+    ImportName(usize),
+
+    /// Continues at the given op.
+    Jump(usize),
+    /// Continues at the given op if the value on top of the stack, which
+    /// is left there, is falsey.
+    JumpIfFalse(usize),
+    /// Throws the value on top of the stack.
+    Throw,
+    /// Pushes the next element of the iterable in the given slot, whose
+    /// cursor is in the slot after it, or jumps to the given op when there
+    /// are none left.
+    ForIter(usize, usize),
+
+    // Superinstructions, fused by the optimizer from common sequences of ops.
+    // The first four are a `Constant` and the binary op that follows it.
+    AddConstant(usize),
+    SubtractConstant(usize),
+    MultiplyConstant(usize),
+    DivideConstant(usize),
+    /// `GetLocal; Constant; Add; SetLocal` on the same slot, as in `i += 1`.
+    /// Adds the constant to the local and pushes the result.
+    AddLocalConstant(usize, usize),
+    /// `GetLocal; GetLocal; Less; JumpIfFalse`. Pushes whether the first
+    /// local is less than the second, and jumps to the op if it isn't.
+    LessLocalsJumpIfFalse(usize, usize, usize),
 
     Return,
 }
@@ -20,23 +89,141 @@ impl OpCode {
     pub fn disassemble(&self, chunk: &Chunk, offset: usize) {
         print!("{:04} ", offset);
 
-        // if offset > 0 {
-        print!("   | ");
-        // }
-        // else {
-        //     print!("{:4} ", chunk.lines[offset]);
-        // }
+        match chunk.line(offset) {
+            Some(line) if offset > 0 && chunk.line(offset - 1) == Some(line) => print!("   | "),
+            Some(line) => print!("{:4} ", line),
+            None => print!("   ? "),
+        }
 
+        println!("{}", self.describe(chunk));
+    }
+
+    /// The op's name and operands, with constants looked up in `chunk`.
+    pub fn describe(&self, chunk: &Chunk) -> String {
         match self {
-            OpCode::Constant(constant_offset) => {
-                println!(
-                    "Constant     {constant_offset} '{:?}'",
-                    &chunk.constants[*constant_offset]
+            OpCode::Constant(constant) => Self::describe_constant("Constant", chunk, *constant),
+            OpCode::AddConstant(constant) => {
+                Self::describe_constant("AddConstant", chunk, *constant)
+            }
+            OpCode::SubtractConstant(constant) => {
+                Self::describe_constant("SubtractConstant", chunk, *constant)
+            }
+            OpCode::MultiplyConstant(constant) => {
+                Self::describe_constant("MultiplyConstant", chunk, *constant)
+            }
+            OpCode::DivideConstant(constant) => {
+                Self::describe_constant("DivideConstant", chunk, *constant)
+            }
+            OpCode::AddLocalConstant(slot, constant) => format!(
+                "{} (slot {slot})",
+                Self::describe_constant("AddLocalConstant", chunk, *constant)
+            ),
+            OpCode::LessLocalsJumpIfFalse(a, b, target) => {
+                format!(
+                    "{:<16} slot {a} < slot {b} else {target}",
+                    "LessLocalsJumpIfFalse"
                 )
             }
-            op => println!("{:?}", op),
+            OpCode::DefineGlobal(name) => Self::describe_constant("DefineGlobal", chunk, *name),
+            OpCode::GetGlobal(name) => Self::describe_constant("GetGlobal", chunk, *name),
+            OpCode::SetGlobal(name) => Self::describe_constant("SetGlobal", chunk, *name),
+            OpCode::Import(path) => Self::describe_constant("Import", chunk, *path),
+            OpCode::ImportName(name) => Self::describe_constant("ImportName", chunk, *name),
+            OpCode::Invoke(name, count) => format!(
+                "{} ({count} args)",
+                Self::describe_constant("Invoke", chunk, *name)
+            ),
+            op => format!("{:?}", op),
+        }
+    }
+
+    /// The constant the op refers to, if any.
+    pub fn constant(&self) -> Option<usize> {
+        match self {
+            OpCode::Constant(constant)
+            | OpCode::AddConstant(constant)
+            | OpCode::SubtractConstant(constant)
+            | OpCode::MultiplyConstant(constant)
+            | OpCode::DivideConstant(constant)
+            | OpCode::AddLocalConstant(_, constant)
+            | OpCode::Invoke(constant, _)
+            | OpCode::DefineGlobal(constant)
+            | OpCode::GetGlobal(constant)
+            | OpCode::SetGlobal(constant)
+            | OpCode::Import(constant)
+            | OpCode::ImportName(constant) => Some(*constant),
+            _ => None,
+        }
+    }
+
+    /// The op with its constant, if it has one, replaced by `constant`.
+    pub fn with_constant(&self, constant: usize) -> OpCode {
+        match self {
+            OpCode::Constant(_) => OpCode::Constant(constant),
+            OpCode::AddConstant(_) => OpCode::AddConstant(constant),
+            OpCode::SubtractConstant(_) => OpCode::SubtractConstant(constant),
+            OpCode::MultiplyConstant(_) => OpCode::MultiplyConstant(constant),
+            OpCode::DivideConstant(_) => OpCode::DivideConstant(constant),
+            OpCode::AddLocalConstant(slot, _) => OpCode::AddLocalConstant(*slot, constant),
+            OpCode::Invoke(_, count) => OpCode::Invoke(constant, *count),
+            OpCode::DefineGlobal(_) => OpCode::DefineGlobal(constant),
+            OpCode::GetGlobal(_) => OpCode::GetGlobal(constant),
+            OpCode::SetGlobal(_) => OpCode::SetGlobal(constant),
+            OpCode::Import(_) => OpCode::Import(constant),
+            OpCode::ImportName(_) => OpCode::ImportName(constant),
+            op => op.clone(),
+        }
+    }
+
+    /// The op the op may continue at instead of the next one, if any.
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            OpCode::Jump(target)
+            | OpCode::JumpIfFalse(target)
+            | OpCode::ForIter(_, target)
+            | OpCode::LessLocalsJumpIfFalse(_, _, target) => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// The op with its jump target, if it has one, replaced by `target`.
+    pub fn with_jump_target(&self, target: usize) -> OpCode {
+        match self {
+            OpCode::Jump(_) => OpCode::Jump(target),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(target),
+            OpCode::ForIter(slot, _) => OpCode::ForIter(*slot, target),
+            OpCode::LessLocalsJumpIfFalse(a, b, _) => OpCode::LessLocalsJumpIfFalse(*a, *b, target),
+            op => op.clone(),
         }
     }
+
+    fn describe_constant(name: &str, chunk: &Chunk, constant_offset: usize) -> String {
+        format!(
+            "{:<16} {constant_offset} '{:?}'",
+            name, &chunk.constants[constant_offset]
+        )
+    }
+}
+
+/// Where errors thrown by the ops from `start` up to `end` are caught. The
+/// stack is cut back to `depth` values, the error pushed and execution
+/// continues at `target`.
+#[derive(Debug, Clone)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub depth: usize,
+}
+
+/// A named local variable, which is in its slot on the stack from the op at
+/// `start` up to `end`. Only kept for debuggers.
+#[derive(Debug, Clone)]
+pub struct LocalVariable {
+    pub name: String,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -45,6 +232,10 @@ pub struct Chunk {
     pub code: Vec<OpCode>,
     pub constants: Vec<Value>,
     lines: Vec<u32>,
+    /// Exception handlers, innermost first where they overlap.
+    pub handlers: Vec<Handler>,
+    /// Named locals, in the order their scopes end.
+    pub locals: Vec<LocalVariable>,
 }
 
 impl Chunk {
@@ -53,12 +244,16 @@ impl Chunk {
             code: vec![],
             constants: vec![],
             lines: vec![],
+            handlers: vec![],
+            locals: vec![],
         }
     }
 
-    pub fn write(&mut self, op: OpCode, line: u32) {
-        self.code.push(op);
-        self.lines.push(line);
+    /// The innermost handler covering the op at `offset`.
+    pub fn handler_at(&self, offset: usize) -> Option<&Handler> {
+        self.handlers
+            .iter()
+            .find(|handler| (handler.start..handler.end).contains(&offset))
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
@@ -72,23 +267,23 @@ impl Chunk {
 
     pub fn disassemble(&self, name: &str) {
         println!("== {} ==", name);
-        let mut offset: usize = 0;
-        for op in &self.code {
+        for (offset, op) in self.code.iter().enumerate() {
             op.disassemble(self, offset);
-            offset += 1;
         }
     }
-    pub fn emit(&mut self, op: OpCode) {
+    pub fn emit(&mut self, op: OpCode, line: u32) {
         self.code.push(op);
+        self.lines.push(line);
     }
 
+    #[allow(dead_code)]
     pub fn emit_many(&mut self, ops: &mut Vec<OpCode>) {
         self.code.append(ops);
     }
 
-    pub fn emit_constant(&mut self, val: Value) {
+    pub fn emit_constant(&mut self, val: Value, line: u32) {
         let constant = self.add_constant(val);
-        self.emit(OpCode::Constant(constant));
+        self.emit(OpCode::Constant(constant), line);
     }
 
     /// Get a reference to the chunk's code.
@@ -96,6 +291,17 @@ impl Chunk {
         self.code.as_ref()
     }
 
+    /// The source line an op was compiled from, if it was recorded.
+    pub fn line(&self, offset: usize) -> Option<u32> {
+        self.lines.get(offset).copied()
+    }
+
+    /// Drops every op from `op_offset` onwards.
+    pub fn truncate(&mut self, op_offset: usize) {
+        self.code.truncate(op_offset);
+        self.lines.truncate(op_offset);
+    }
+
     pub fn op_count(&self) -> usize {
         self.code.len()
     }
@@ -1,20 +1,136 @@
 use crate::{
-    chunk::Chunk,
-    chunk::OpCode,
+    chunk::{Chunk, Handler, LocalVariable, OpCode},
     objects::StringObject,
+    optimizer,
     precedence::Precedence,
-    scanner::Scanner,
+    scanner::{self, Scanner},
     token::{TokenResult, TokenType},
     value::Value,
+    vm,
 };
-use std::rc::Rc;
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Where an error happened as printed before its message: `[path:line]`, or
+/// `[line N]` for source that wasn't read from a file.
+pub fn location(file: Option<&Path>, line: u32) -> String {
+    match file {
+        Some(file) => format!("[{}:{line}]", file.display()),
+        None => format!("[line {line}]"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: u32,
+    pub message: String,
+    /// Warnings don't stop the program from compiling.
+    pub warning: bool,
+}
+
+#[derive(Debug)]
+struct Local<'a> {
+    name: &'a str,
+    /// Depth of the scope it was declared in, or `None` until its
+    /// initializer has been compiled.
+    depth: Option<usize>,
+    /// The first op that runs with the local on the stack.
+    start: usize,
+}
+
+#[derive(Debug)]
+struct Loop {
+    /// Where `continue` jumps to.
+    start: usize,
+    /// Locals deeper than this belong to the loop and are popped on the way
+    /// out of it.
+    scope_depth: usize,
+    /// `break` jumps, patched once the loop's exit is known.
+    breaks: Vec<usize>,
+    /// How many try statements were already open when the loop started.
+    tries: usize,
+}
+
+/// A `match` arm pattern other than `_`.
+#[derive(Debug)]
+enum Pattern {
+    Literal(Value),
+    /// A range of numbers, with whether it includes its end.
+    Range(Value, Value, bool),
+}
+
+impl Pattern {
+    /// Emits a test of the subject on top of the stack, which leaves whether
+    /// the subject matched in its place.
+    fn emit_test(&self, frame: &mut Chunk, line: u32) {
+        match self {
+            Pattern::Literal(value) => {
+                frame.emit_constant(value.clone(), line);
+                frame.emit(OpCode::Equal, line);
+            }
+            Pattern::Range(start, end, inclusive) => {
+                frame.emit_constant(start.clone(), line);
+                frame.emit_constant(end.clone(), line);
+                frame.emit(OpCode::InRange(*inclusive), line);
+            }
+        }
+    }
+
+    /// Whether everything `other` matches is matched by this pattern too.
+    fn covers(&self, other: &Pattern) -> bool {
+        match (self, other) {
+            (Pattern::Literal(a), Pattern::Literal(b)) => a == b,
+            (Pattern::Literal(_), Pattern::Range(..)) => false,
+            (Pattern::Range(..), Pattern::Literal(value)) => self.contains(value),
+            (
+                Pattern::Range(_, end, inclusive),
+                Pattern::Range(other_start, other_end, other_inclusive),
+            ) => {
+                self.contains(other_start)
+                    && (self.contains(other_end)
+                        || other_end == end && (*inclusive || !other_inclusive))
+            }
+        }
+    }
+
+    fn contains(&self, value: &Value) -> bool {
+        let Pattern::Range(start, end, inclusive) = self else {
+            return false;
+        };
+        match (value.as_float(), start.as_float(), end.as_float()) {
+            (Some(value), Some(start), Some(end)) => {
+                start <= value && (value < end || *inclusive && value == end)
+            }
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Compiler<'a> {
     scanner: Scanner<'a>,
     previous: TokenResult<'a>,
     current: TokenResult<'a>,
     pub had_error: bool,
+    /// Every error found so far, in source order.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Print errors to stderr as they are found.
+    pub print_errors: bool,
+    /// The file the source was read from, named in printed errors.
+    pub file: Option<PathBuf>,
     panic_mode: bool,
+    /// Run the peephole optimizer over the compiled chunk.
+    pub optimize: bool,
+    /// Locals in scope, innermost last. A local's index is its stack slot.
+    locals: Vec<Local<'a>>,
+    scope_depth: usize,
+    /// Loops being compiled, innermost last.
+    loops: Vec<Loop>,
+    /// For each try statement whose try or catch block is being compiled,
+    /// innermost last, the lines of any `break` or `continue` leaving it.
+    tries: Vec<Vec<i32>>,
 }
 
 impl<'a> Compiler<'a> {
@@ -24,7 +140,15 @@ impl<'a> Compiler<'a> {
             previous: TokenResult::invalid(),
             current: TokenResult::invalid(),
             had_error: false,
+            diagnostics: vec![],
+            print_errors: true,
+            file: None,
             panic_mode: false,
+            optimize: true,
+            locals: vec![],
+            scope_depth: 0,
+            loops: vec![],
+            tries: vec![],
         }
     }
 
@@ -32,17 +156,28 @@ impl<'a> Compiler<'a> {
         let mut frame = Chunk::new();
         self.advance();
 
-        if !self.matches(TokenType::Eof) {
-            self.expression(&mut frame);
+        let mut has_value = false;
+        while !self.matches(TokenType::Eof) {
+            has_value = self.declaration(&mut frame);
+        }
+        if !has_value {
+            frame.emit(OpCode::Nil, self.line());
         }
 
         self.emit_return(&mut frame);
 
-        // #[cfg(feature = "debug_print_code")]
+        #[cfg(feature = "debug_print_code")]
         if !self.had_error {
             frame.disassemble("code");
         }
 
+        if self.optimize && !self.had_error {
+            frame = optimizer::optimize(&frame);
+
+            #[cfg(feature = "debug_print_code")]
+            frame.disassemble("optimized");
+        }
+
         frame
     }
 
@@ -53,7 +188,7 @@ impl<'a> Compiler<'a> {
             self.current = self.scanner.scan_token();
             match &self.current.data.clone() {
                 Ok(_) => break,
-                Err(message) => self.error_at_current(&message),
+                Err(message) => self.error_at_current(message),
             }
         }
     }
@@ -75,8 +210,12 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    fn line(&self) -> u32 {
+        self.previous.line as u32
+    }
+
     fn emit_return(&self, frame: &mut Chunk) {
-        frame.emit(OpCode::Return)
+        frame.emit(OpCode::Return, self.line())
     }
 
     fn error_at_current(&mut self, message: &str) {
@@ -85,31 +224,768 @@ impl<'a> Compiler<'a> {
     fn error_at(&mut self, line: i32, message: &str) {
         if !self.panic_mode {
             self.panic_mode = true;
-            println!(
-                "
-                [line {}] Error: {}
-                Compiler state: {:#?}
-                ",
-                line, message, self
-            );
+            if self.print_errors {
+                let location = location(self.file.as_deref(), line.max(1) as u32);
+                eprintln!("{location} Error: {message}");
+                #[cfg(feature = "debug_print_code")]
+                eprintln!("Compiler state: {:#?}", self);
+            }
+            self.diagnostics.push(Diagnostic {
+                line: line.max(1) as u32,
+                message: message.to_string(),
+                warning: false,
+            });
             self.had_error = true;
         }
     }
 
+    fn warning_at(&mut self, line: i32, message: &str) {
+        if self.print_errors {
+            let location = location(self.file.as_deref(), line.max(1) as u32);
+            eprintln!("{location} Warning: {message}");
+        }
+        self.diagnostics.push(Diagnostic {
+            line: line.max(1) as u32,
+            message: message.to_string(),
+            warning: true,
+        });
+    }
+
+    /// Compiles a declaration, returning whether it was an expression ending
+    /// the script without a `;`, whose value is left as the script's result.
+    fn declaration(&mut self, frame: &mut Chunk) -> bool {
+        let has_value = if self.matches(TokenType::Var) {
+            self.var_declaration(frame);
+            false
+        } else if self.matches(TokenType::Import) {
+            self.import_declaration(frame);
+            false
+        } else if self.matches(TokenType::From) {
+            self.import_names_declaration(frame);
+            false
+        } else {
+            self.statement(frame)
+        };
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+        has_value
+    }
+
+    fn var_declaration(&mut self, frame: &mut Chunk) {
+        self.consume(TokenType::Identifier, "Expect variable name.");
+        let name = self.lexeme();
+        self.declare_variable(name);
+
+        if self.matches(TokenType::Equal) {
+            self.expression(frame);
+        } else {
+            frame.emit(OpCode::Nil, self.line());
+        }
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+
+        self.define_variable(name, frame);
+    }
+
+    /// `import "path" as name;` binds the module at `path` to `name`.
+    fn import_declaration(&mut self, frame: &mut Chunk) {
+        let path = self.module_path();
+        self.consume(TokenType::As, "Expect 'as' after module path.");
+        self.consume(TokenType::Identifier, "Expect module name.");
+        let name = self.lexeme();
+        self.declare_variable(name);
+        let path = self.identifier_constant(&path, frame);
+        frame.emit(OpCode::Import(path), self.line());
+        self.consume(TokenType::Semicolon, "Expect ';' after import.");
+        self.define_variable(name, frame);
+    }
+
+    /// `from "path" import a, b;` binds names the module at `path` defines.
+    fn import_names_declaration(&mut self, frame: &mut Chunk) {
+        let path = self.module_path();
+        self.consume(TokenType::Import, "Expect 'import' after module path.");
+        loop {
+            self.consume(TokenType::Identifier, "Expect name to import.");
+            let name = self.lexeme();
+            self.declare_variable(name);
+            // Modules are cached, so importing one again for every name is
+            // cheap.
+            let path = self.identifier_constant(&path, frame);
+            frame.emit(OpCode::Import(path), self.line());
+            let constant = self.identifier_constant(name, frame);
+            frame.emit(OpCode::ImportName(constant), self.line());
+            self.define_variable(name, frame);
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after import.");
+    }
+
+    fn module_path(&mut self) -> String {
+        self.consume(TokenType::String, "Expect module path.");
+        scanner::unescape(scanner::string_contents(self.lexeme())).unwrap_or_else(|message| {
+            self.error_at(self.previous.line, &message);
+            String::new()
+        })
+    }
+
+    /// Locals are declared before their initializer is compiled, so that it
+    /// can't refer to them.
+    fn declare_variable(&mut self, name: &'a str) {
+        if self.scope_depth > 0 {
+            self.add_local(name);
+        }
+    }
+
+    /// Makes the value on top of the stack the variable's, once it has been
+    /// computed.
+    fn define_variable(&mut self, name: &str, frame: &mut Chunk) {
+        if self.scope_depth > 0 {
+            self.mark_initialized(frame);
+        } else {
+            let name = self.identifier_constant(name, frame);
+            frame.emit(OpCode::DefineGlobal(name), self.line());
+        }
+    }
+
+    fn statement(&mut self, frame: &mut Chunk) -> bool {
+        if self.matches(TokenType::For) {
+            self.for_in_statement(frame);
+        } else if self.matches(TokenType::Match) {
+            self.match_statement(frame);
+        } else if self.matches(TokenType::Try) {
+            self.try_statement(frame);
+        } else if self.matches(TokenType::Throw) {
+            self.throw_statement(frame);
+        } else if self.matches(TokenType::Break) {
+            self.break_statement(frame);
+        } else if self.matches(TokenType::Continue) {
+            self.continue_statement(frame);
+        } else if self.matches(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block(frame);
+            self.end_scope(frame);
+        } else {
+            return self.expression_statement(frame);
+        }
+        false
+    }
+
+    fn block(&mut self, frame: &mut Chunk) {
+        while !matches!(
+            self.current.token_type,
+            TokenType::RightBrace | TokenType::Eof
+        ) {
+            self.declaration(frame);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+    }
+
+    /// `for (name in iterable) body`. The iterable and a cursor into it are
+    /// kept in hidden locals below the loop variable for the whole loop.
+    fn for_in_statement(&mut self, frame: &mut Chunk) {
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        self.consume(TokenType::Identifier, "Expect loop variable name.");
+        let name = self.lexeme();
+        self.consume(TokenType::In, "Expect 'in' after loop variable.");
+        self.expression(frame);
+        self.consume(TokenType::RightParen, "Expect ')' after loop iterable.");
+
+        let iterable = self.locals.len();
+        self.add_local("");
+        self.mark_initialized(frame);
+        frame.emit_constant(Value::Integer(0), self.line());
+        self.add_local("");
+        self.mark_initialized(frame);
+
+        let start = frame.op_count();
+        // The exit is patched in once the body is compiled.
+        frame.emit(OpCode::ForIter(iterable, start), self.line());
+        self.loops.push(Loop {
+            start,
+            scope_depth: self.scope_depth,
+            breaks: vec![],
+            tries: self.tries.len(),
+        });
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized(frame);
+        self.statement(frame);
+        self.end_scope(frame);
+        frame.emit(OpCode::Jump(start), self.line());
+
+        Self::patch_jump(start, frame);
+        let breaks = self
+            .loops
+            .pop()
+            .map_or(vec![], |innermost| innermost.breaks);
+        for jump in breaks {
+            Self::patch_jump(jump, frame);
+        }
+        self.end_scope(frame);
+    }
+
+    /// `match (subject) { patterns if guard => statement ... }`, where the
+    /// guard is optional. Arms are tried in order against the subject, kept
+    /// in a hidden local, and the first one that matches and whose guard
+    /// holds is run. An arm's patterns are literals, ranges like `1..10` or
+    /// `1..=9`, alternatives separated by `|`, or `_` for anything.
+    fn match_statement(&mut self, frame: &mut Chunk) {
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'match'.");
+        self.expression(frame);
+        self.consume(TokenType::RightParen, "Expect ')' after match subject.");
+        let subject = self.locals.len();
+        self.add_local("");
+        self.mark_initialized(frame);
+        self.consume(TokenType::LeftBrace, "Expect '{' before match arms.");
+
+        // Patterns of the arms without guards so far, and whether one of
+        // them was `_`, to find arms that can never run.
+        let mut covered: Vec<Pattern> = vec![];
+        let mut exhaustive = false;
+        let mut exits = vec![];
+        while !matches!(
+            self.current.token_type,
+            TokenType::RightBrace | TokenType::Eof
+        ) {
+            let line = self.current.line;
+            let wildcard = self.current.token_type == TokenType::Identifier
+                && self
+                    .current
+                    .data
+                    .as_ref()
+                    .is_ok_and(|data| data.lexeme == "_");
+            // Each test leaves whether it passed on the stack, and these
+            // jumps skip the arm when it didn't.
+            let mut no_match = vec![];
+            let mut patterns = vec![];
+            if wildcard {
+                self.advance();
+            } else {
+                let mut matched = vec![];
+                loop {
+                    let pattern = self.pattern();
+                    frame.emit(OpCode::GetLocal(subject), self.line());
+                    pattern.emit_test(frame, self.line());
+                    patterns.push(pattern);
+                    if !self.matches(TokenType::Pipe) {
+                        break;
+                    }
+                    let next = frame.op_count();
+                    frame.emit(OpCode::JumpIfFalse(0), self.line());
+                    matched.push(frame.op_count());
+                    frame.emit(OpCode::Jump(0), self.line());
+                    Self::patch_jump(next, frame);
+                    frame.emit(OpCode::Pop, self.line());
+                }
+                no_match.push(frame.op_count());
+                frame.emit(OpCode::JumpIfFalse(0), self.line());
+                for jump in matched {
+                    Self::patch_jump(jump, frame);
+                }
+                frame.emit(OpCode::Pop, self.line());
+            }
+
+            let guarded = self.matches(TokenType::If);
+            if guarded {
+                self.expression(frame);
+                no_match.push(frame.op_count());
+                frame.emit(OpCode::JumpIfFalse(0), self.line());
+                frame.emit(OpCode::Pop, self.line());
+            }
+            self.consume(TokenType::EqualGreater, "Expect '=>' after match pattern.");
+
+            let unreachable = exhaustive
+                || !patterns.is_empty()
+                    && patterns
+                        .iter()
+                        .all(|pattern| covered.iter().any(|earlier| earlier.covers(pattern)));
+            if unreachable {
+                self.warning_at(line, "Unreachable match arm.");
+            }
+            if !guarded {
+                exhaustive |= wildcard;
+                covered.extend(patterns);
+            }
+
+            self.statement(frame);
+            exits.push(frame.op_count());
+            frame.emit(OpCode::Jump(0), self.line());
+            if !no_match.is_empty() {
+                for jump in no_match {
+                    Self::patch_jump(jump, frame);
+                }
+                frame.emit(OpCode::Pop, self.line());
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.");
+
+        for jump in exits {
+            Self::patch_jump(jump, frame);
+        }
+        self.end_scope(frame);
+    }
+
+    /// A literal, or a range between two number literals.
+    fn pattern(&mut self) -> Pattern {
+        let start = self.pattern_literal();
+        let inclusive = match self.current.token_type {
+            TokenType::DotDot => false,
+            TokenType::DotDotEqual => true,
+            _ => return Pattern::Literal(start),
+        };
+        self.advance();
+        let end = self.pattern_literal();
+        if start.as_float().is_none() || end.as_float().is_none() {
+            self.error_at(self.previous.line, "Range bounds must be numbers.");
+        }
+        Pattern::Range(start, end, inclusive)
+    }
+
+    fn pattern_literal(&mut self) -> Value {
+        let negative = self.matches(TokenType::Minus);
+        self.advance();
+        let value = match self.previous.token_type {
+            TokenType::Number => scanner::parse_number(self.lexeme()),
+            TokenType::String => scanner::unescape(scanner::string_contents(self.lexeme()))
+                .map(|value| Value::String(Rc::from(StringObject::from_owned(value)))),
+            TokenType::True => Ok(Value::Boolean(true)),
+            TokenType::False => Ok(Value::Boolean(false)),
+            TokenType::Nil => Ok(Value::Nil),
+            _ => Err("Expect pattern.".to_string()),
+        };
+        let value = match value {
+            Ok(value) if negative => vm::negate(&value).map_err(|error| error.to_string()),
+            value => value,
+        };
+        value.unwrap_or_else(|message| {
+            self.error_at(self.previous.line, &message);
+            Value::Nil
+        })
+    }
+
+    /// Points the forward jump at `jump` to the next op to be emitted.
+    fn patch_jump(jump: usize, frame: &mut Chunk) {
+        let op = frame.code()[jump].with_jump_target(frame.op_count());
+        frame.op_patch(jump, op);
+    }
+
+    fn break_statement(&mut self, frame: &mut Chunk) {
+        if self.loops.is_empty() {
+            self.error_at(self.previous.line, "Can't use 'break' outside of a loop.");
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.");
+        self.pop_loop_locals(frame);
+        self.escape_tries();
+        let jump = frame.op_count();
+        // Patched to the loop's exit at the end of the loop.
+        frame.emit(OpCode::Jump(0), self.line());
+        if let Some(innermost) = self.loops.last_mut() {
+            innermost.breaks.push(jump);
+        }
+    }
+
+    fn continue_statement(&mut self, frame: &mut Chunk) {
+        if self.loops.is_empty() {
+            self.error_at(
+                self.previous.line,
+                "Can't use 'continue' outside of a loop.",
+            );
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.");
+        self.pop_loop_locals(frame);
+        self.escape_tries();
+        if let Some(innermost) = self.loops.last() {
+            frame.emit(OpCode::Jump(innermost.start), self.line());
+        }
+    }
+
+    /// Notes a `break` or `continue` on the try statements opened inside the
+    /// innermost loop, which it leaves.
+    fn escape_tries(&mut self) {
+        let Some(innermost) = self.loops.last() else {
+            return;
+        };
+        let line = self.previous.line;
+        for escapes in &mut self.tries[innermost.tries..] {
+            escapes.push(line);
+        }
+    }
+
+    /// `try { ... } catch (e) { ... } finally { ... }`, with a catch block, a
+    /// finally block or both. Errors thrown in the try block are caught by
+    /// the catch block. The finally block runs after the others however they
+    /// end, and then rethrows the error that escaped them, if any.
+    ///
+    /// Handlers cover the try block and, when there's a finally, the catch
+    /// block. A finally block is entered with the pending error, or nil, and
+    /// whether there is one in two hidden locals.
+    fn try_statement(&mut self, frame: &mut Chunk) {
+        let depth = self.locals.len();
+        self.tries.push(vec![]);
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.");
+        let start = frame.op_count();
+        self.begin_scope();
+        self.block(frame);
+        self.end_scope(frame);
+        let end = frame.op_count();
+        let mut normal_exits = vec![frame.op_count()];
+        frame.emit(OpCode::Jump(0), self.line());
+
+        let mut catch = None;
+        if self.matches(TokenType::Catch) {
+            let target = frame.op_count();
+            frame.handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+            self.begin_scope();
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.");
+            self.consume(TokenType::Identifier, "Expect error variable name.");
+            self.add_local(self.lexeme());
+            self.mark_initialized(frame);
+            self.consume(TokenType::RightParen, "Expect ')' after error variable.");
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.");
+            self.block(frame);
+            self.end_scope(frame);
+            catch = Some((target, frame.op_count()));
+            normal_exits.push(frame.op_count());
+            frame.emit(OpCode::Jump(0), self.line());
+        }
+        let escapes = self.tries.pop().unwrap_or_default();
+
+        if !self.matches(TokenType::Finally) {
+            if catch.is_none() {
+                self.error_at_current("Expect 'catch' or 'finally' after try block.");
+            }
+            for jump in normal_exits {
+                Self::patch_jump(jump, frame);
+            }
+            return;
+        }
+        if let Some(&line) = escapes.first() {
+            self.error_at(line, "Can't jump out of a 'try' that has a 'finally'.");
+        }
+
+        let (start, end) = catch.unwrap_or((start, end));
+        let target = frame.op_count();
+        frame.handlers.push(Handler {
+            start,
+            end,
+            target,
+            depth,
+        });
+        frame.emit(OpCode::True, self.line());
+        let thrown = frame.op_count();
+        frame.emit(OpCode::Jump(0), self.line());
+        for jump in normal_exits {
+            Self::patch_jump(jump, frame);
+        }
+        frame.emit(OpCode::Nil, self.line());
+        frame.emit(OpCode::False, self.line());
+        Self::patch_jump(thrown, frame);
+
+        self.begin_scope();
+        self.add_local("");
+        self.mark_initialized(frame);
+        self.add_local("");
+        self.mark_initialized(frame);
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.");
+        self.block(frame);
+        frame.emit(OpCode::GetLocal(depth + 1), self.line());
+        let done = frame.op_count();
+        frame.emit(OpCode::JumpIfFalse(0), self.line());
+        frame.emit(OpCode::GetLocal(depth), self.line());
+        frame.emit(OpCode::Throw, self.line());
+        Self::patch_jump(done, frame);
+        frame.emit(OpCode::Pop, self.line());
+        self.end_scope(frame);
+    }
+
+    fn throw_statement(&mut self, frame: &mut Chunk) {
+        self.expression(frame);
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.");
+        frame.emit(OpCode::Throw, self.line());
+    }
+
+    /// Pops the locals declared inside the innermost loop, leaving them in
+    /// scope for the code that follows.
+    fn pop_loop_locals(&mut self, frame: &mut Chunk) {
+        let Some(innermost) = self.loops.last() else {
+            return;
+        };
+        let count = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| {
+                local
+                    .depth
+                    .is_none_or(|depth| depth > innermost.scope_depth)
+            })
+            .count();
+        for _ in 0..count {
+            frame.emit(OpCode::Pop, self.line());
+        }
+    }
+
+    fn expression_statement(&mut self, frame: &mut Chunk) -> bool {
+        self.expression(frame);
+        if self.scope_depth == 0 && self.current.token_type == TokenType::Eof {
+            return true;
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+        frame.emit(OpCode::Pop, self.line());
+        false
+    }
+
+    /// Skips to what looks like the start of the next statement, so one
+    /// mistake doesn't cause a cascade of errors.
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+        while self.current.token_type != TokenType::Eof {
+            if self.previous.token_type == TokenType::Semicolon {
+                return;
+            }
+            match self.current.token_type {
+                TokenType::Break
+                | TokenType::Class
+                | TokenType::Continue
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try
+                | TokenType::Import
+                | TokenType::From => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, frame: &mut Chunk) {
+        self.scope_depth -= 1;
+        while self
+            .locals
+            .last()
+            .is_some_and(|local| local.depth.is_none_or(|depth| depth > self.scope_depth))
+        {
+            let local = self.locals.pop().unwrap();
+            if !local.name.is_empty() {
+                frame.locals.push(LocalVariable {
+                    name: local.name.to_string(),
+                    slot: self.locals.len(),
+                    start: local.start,
+                    end: frame.op_count(),
+                });
+            }
+            frame.emit(OpCode::Pop, self.line());
+        }
+    }
+
+    fn add_local(&mut self, name: &'a str) {
+        let duplicate = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth == self.scope_depth))
+            .any(|local| local.name == name && !name.is_empty());
+        if duplicate {
+            self.error_at(
+                self.previous.line,
+                "Already a variable with this name in this scope.",
+            );
+        }
+        self.locals.push(Local {
+            name,
+            depth: None,
+            start: 0,
+        });
+    }
+
+    fn mark_initialized(&mut self, frame: &Chunk) {
+        if let Some(local) = self.locals.last_mut() {
+            local.depth = Some(self.scope_depth);
+            local.start = frame.op_count();
+        }
+    }
+
+    fn resolve_local(&mut self, name: &str) -> Option<usize> {
+        let slot = self.locals.iter().rposition(|local| local.name == name)?;
+        if self.locals[slot].depth.is_none() {
+            self.error_at(
+                self.previous.line,
+                "Can't read local variable in its own initializer.",
+            );
+        }
+        Some(slot)
+    }
+
+    fn identifier_constant(&self, name: &str, frame: &mut Chunk) -> usize {
+        let name = StringObject::from_owned(name.to_string());
+        frame.add_constant(Value::String(Rc::from(name)))
+    }
+
+    /// The previous token's lexeme, or nothing if it was an error.
+    fn lexeme(&self) -> &'a str {
+        self.previous.data.as_ref().map_or("", |data| data.lexeme)
+    }
+
     fn expression(&mut self, frame: &mut Chunk) {
         self.parse_precedence(Precedence::Assignment, frame)
     }
 
+    fn variable(&mut self, can_assign: bool, frame: &mut Chunk) {
+        let name = self.lexeme();
+        let (get, set) = match self.resolve_local(name) {
+            Some(slot) => (OpCode::GetLocal(slot), OpCode::SetLocal(slot)),
+            None => {
+                let name = self.identifier_constant(name, frame);
+                (OpCode::GetGlobal(name), OpCode::SetGlobal(name))
+            }
+        };
+
+        let compound = Self::compound_assignment(self.current.token_type);
+        if can_assign && self.matches(TokenType::Equal) {
+            self.expression(frame);
+            frame.emit(set, self.line());
+        } else if let (true, Some(op)) = (can_assign, compound) {
+            self.advance();
+            frame.emit(get, self.line());
+            self.expression(frame);
+            frame.emit(op, self.line());
+            frame.emit(set, self.line());
+        } else {
+            frame.emit(get, self.line());
+        }
+    }
+
     fn number(&mut self, frame: &mut Chunk) {
         let data = self.previous.data.as_ref().unwrap();
-        let val = data.lexeme.parse::<f64>().unwrap();
-        frame.emit_constant(Value::Number(val))
+        match scanner::parse_number(data.lexeme) {
+            Ok(val) => frame.emit_constant(val, self.line()),
+            Err(message) => self.error_at(self.previous.line, &message),
+        }
     }
 
     fn string(&mut self, frame: &mut Chunk) {
         let data = self.previous.data.as_ref().unwrap();
-        let val = StringObject::new(data.lexeme);
-        frame.emit_constant(Value::String(Rc::from(val)));
+        match scanner::unescape(scanner::string_contents(data.lexeme)) {
+            Ok(value) => {
+                let val = StringObject::from_owned(value);
+                frame.emit_constant(Value::String(Rc::from(val)), self.line());
+            }
+            Err(message) => self.error_at(self.previous.line, &message),
+        }
+    }
+
+    /// `"a ${b} c"` arrives as an `Interpolation` token for `a `, the tokens
+    /// of `b` and a `String` token for ` c`, and is compiled to their
+    /// concatenation with every expression turned into a string.
+    fn interpolation(&mut self, frame: &mut Chunk) {
+        self.string(frame);
+        loop {
+            self.expression(frame);
+            frame.emit(OpCode::Stringify, self.line());
+            frame.emit(OpCode::Add, self.line());
+
+            let more = self.matches(TokenType::Interpolation);
+            if !more && !self.matches(TokenType::String) {
+                self.error_at_current("Expect '}' after interpolated expression.");
+                return;
+            }
+            if !scanner::string_contents(self.lexeme()).is_empty() {
+                self.string(frame);
+                frame.emit(OpCode::Add, self.line());
+            }
+            if !more {
+                return;
+            }
+        }
+    }
+
+    fn list(&mut self, frame: &mut Chunk) {
+        let mut count = 0;
+        while self.current.token_type != TokenType::RightBracket {
+            self.expression(frame);
+            count += 1;
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.");
+        frame.emit(OpCode::BuildList(count), self.line());
+    }
+
+    fn map(&mut self, frame: &mut Chunk) {
+        let mut count = 0;
+        while self.current.token_type != TokenType::RightBrace {
+            self.expression(frame);
+            self.consume(TokenType::Colon, "Expect ':' after map key.");
+            self.expression(frame);
+            count += 1;
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+        frame.emit(OpCode::BuildMap(count), self.line());
+    }
+
+    fn index(&mut self, can_assign: bool, frame: &mut Chunk) {
+        self.expression(frame);
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+
+        let compound = Self::compound_assignment(self.current.token_type);
+        if can_assign && self.matches(TokenType::Equal) {
+            self.expression(frame);
+            frame.emit(OpCode::SetIndex, self.line());
+        } else if let (true, Some(op)) = (can_assign, compound) {
+            self.advance();
+            frame.emit(OpCode::DuplicatePair, self.line());
+            frame.emit(OpCode::Index, self.line());
+            self.expression(frame);
+            frame.emit(op, self.line());
+            frame.emit(OpCode::SetIndex, self.line());
+        } else {
+            frame.emit(OpCode::Index, self.line());
+        }
+    }
+
+    /// `receiver.name(arguments)`. Only the built-in methods exist, so a
+    /// name is always called.
+    fn method(&mut self, frame: &mut Chunk) {
+        self.consume(TokenType::Identifier, "Expect method name after '.'.");
+        let name = self.identifier_constant(self.lexeme(), frame);
+        self.consume(TokenType::LeftParen, "Expect '(' after method name.");
+
+        let mut count = 0;
+        while self.current.token_type != TokenType::RightParen {
+            self.expression(frame);
+            count += 1;
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        frame.emit(OpCode::Invoke(name, count), self.line());
     }
 
     fn grouping(&mut self, frame: &mut Chunk) {
@@ -119,9 +995,9 @@ impl<'a> Compiler<'a> {
 
     fn literal(&mut self, frame: &mut Chunk) {
         match self.previous.token_type {
-            TokenType::True => frame.emit(OpCode::True),
-            TokenType::False => frame.emit(OpCode::False),
-            TokenType::Nil => frame.emit(OpCode::Nil),
+            TokenType::True => frame.emit(OpCode::True, self.line()),
+            TokenType::False => frame.emit(OpCode::False, self.line()),
+            TokenType::Nil => frame.emit(OpCode::Nil, self.line()),
             _ => panic!(
                 "Expected a literal but found {:?}",
                 self.previous.token_type
@@ -135,32 +1011,91 @@ impl<'a> Compiler<'a> {
         self.parse_precedence(Precedence::Unary, frame);
 
         match operator_type {
-            TokenType::Minus => frame.emit(OpCode::Negate),
-            TokenType::Bang => frame.emit(OpCode::Not),
+            TokenType::Minus => frame.emit(OpCode::Negate, self.line()),
+            TokenType::Bang => frame.emit(OpCode::Not, self.line()),
             _ => (),
         }
     }
 
     fn binary(&mut self, frame: &mut Chunk) {
         let operator_type = self.previous.token_type;
-        self.parse_precedence(Self::get_precedence(operator_type).next(), frame);
+        self.parse_precedence(Self::get_precedence(operator_type).right_operand(), frame);
         match operator_type {
-            TokenType::Plus => frame.emit(OpCode::Add),
-            TokenType::Minus => frame.emit(OpCode::Subtract),
-            TokenType::Star => frame.emit(OpCode::Multiply),
-            TokenType::Slash => frame.emit(OpCode::Divide),
+            TokenType::Plus => frame.emit(OpCode::Add, self.line()),
+            TokenType::Minus => frame.emit(OpCode::Subtract, self.line()),
+            TokenType::Star => frame.emit(OpCode::Multiply, self.line()),
+            TokenType::Slash => frame.emit(OpCode::Divide, self.line()),
+            TokenType::Percent => frame.emit(OpCode::Modulo, self.line()),
+            TokenType::StarStar => frame.emit(OpCode::Power, self.line()),
+            TokenType::Ampersand => frame.emit(OpCode::BitAnd, self.line()),
+            TokenType::Pipe => frame.emit(OpCode::BitOr, self.line()),
+            TokenType::Caret => frame.emit(OpCode::BitXor, self.line()),
+            TokenType::LessLess => frame.emit(OpCode::ShiftLeft, self.line()),
+            TokenType::GreaterGreater => frame.emit(OpCode::ShiftRight, self.line()),
+            TokenType::EqualEqual => frame.emit(OpCode::Equal, self.line()),
+            TokenType::BangEqual => {
+                frame.emit(OpCode::Equal, self.line());
+                frame.emit(OpCode::Not, self.line());
+            }
+            TokenType::Greater => frame.emit(OpCode::Greater, self.line()),
+            TokenType::GreaterEqual => frame.emit(OpCode::GreaterEqual, self.line()),
+            TokenType::Less => frame.emit(OpCode::Less, self.line()),
+            TokenType::LessEqual => frame.emit(OpCode::LessEqual, self.line()),
             _ => (),
         }
     }
 
+    /// Skips the right operand, leaving the left one as the result, if the
+    /// left one is falsey.
+    fn and(&mut self, frame: &mut Chunk) {
+        let end = frame.op_count();
+        frame.emit(OpCode::JumpIfFalse(0), self.line());
+        frame.emit(OpCode::Pop, self.line());
+        self.parse_precedence(Precedence::And.next(), frame);
+        Self::patch_jump(end, frame);
+    }
+
+    /// Skips the right operand, leaving the left one as the result, if the
+    /// left one is truthy.
+    fn or(&mut self, frame: &mut Chunk) {
+        let right = frame.op_count();
+        frame.emit(OpCode::JumpIfFalse(0), self.line());
+        let end = frame.op_count();
+        frame.emit(OpCode::Jump(0), self.line());
+        Self::patch_jump(right, frame);
+        frame.emit(OpCode::Pop, self.line());
+        self.parse_precedence(Precedence::Or.next(), frame);
+        Self::patch_jump(end, frame);
+    }
+
     fn parse_precedence(&mut self, precedence: Precedence, frame: &mut Chunk) {
         self.advance();
 
-        self.prefix_rule(self.previous.token_type, frame);
+        let can_assign = precedence <= Precedence::Assignment;
+        self.prefix_rule(self.previous.token_type, can_assign, frame);
 
         while precedence <= Self::get_precedence(self.current.token_type) {
             self.advance();
-            self.infix_rule(self.previous.token_type, frame);
+            self.infix_rule(self.previous.token_type, can_assign, frame);
+        }
+
+        // Assignable targets have consumed their `=` by now.
+        let assignment = self.current.token_type == TokenType::Equal
+            || Self::compound_assignment(self.current.token_type).is_some();
+        if can_assign && assignment {
+            self.error_at_current("Invalid assignment target.");
+        }
+    }
+
+    /// The op a compound assignment like `+=` applies before assigning.
+    fn compound_assignment(token_type: TokenType) -> Option<OpCode> {
+        match token_type {
+            TokenType::PlusEqual => Some(OpCode::Add),
+            TokenType::MinusEqual => Some(OpCode::Subtract),
+            TokenType::StarEqual => Some(OpCode::Multiply),
+            TokenType::SlashEqual => Some(OpCode::Divide),
+            TokenType::PercentEqual => Some(OpCode::Modulo),
+            _ => None,
         }
     }
 
@@ -170,6 +1105,13 @@ impl<'a> Compiler<'a> {
             TokenType::Plus => Precedence::Term,
             TokenType::Slash => Precedence::Factor,
             TokenType::Star => Precedence::Factor,
+            TokenType::Percent => Precedence::Factor,
+            TokenType::StarStar => Precedence::Exponent,
+            TokenType::LessLess => Precedence::Shift,
+            TokenType::GreaterGreater => Precedence::Shift,
+            TokenType::Ampersand => Precedence::BitAnd,
+            TokenType::Caret => Precedence::BitXor,
+            TokenType::Pipe => Precedence::BitOr,
             TokenType::BangEqual => Precedence::Equality,
             TokenType::EqualEqual => Precedence::Equality,
             TokenType::Greater => Precedence::Comparison,
@@ -179,12 +1121,15 @@ impl<'a> Compiler<'a> {
             TokenType::And => Precedence::And,
             TokenType::Or => Precedence::Or,
             TokenType::LeftParen => Precedence::Call,
+            TokenType::LeftBracket => Precedence::Call,
+            TokenType::Dot => Precedence::Call,
             _ => Precedence::None,
         }
     }
 
-    fn prefix_rule(&mut self, operator_type: TokenType, frame: &mut Chunk) {
+    fn prefix_rule(&mut self, operator_type: TokenType, can_assign: bool, frame: &mut Chunk) {
         match operator_type {
+            TokenType::Identifier => self.variable(can_assign, frame),
             TokenType::LeftParen => self.grouping(frame),
             TokenType::Minus => self.unary(frame),
             TokenType::Number => self.number(frame),
@@ -193,22 +1138,36 @@ impl<'a> Compiler<'a> {
             TokenType::Nil => self.literal(frame),
             TokenType::Bang => self.unary(frame),
             TokenType::String => self.string(frame),
-            tt => panic!("Expected expresion, got {:?}", tt),
+            TokenType::Interpolation => self.interpolation(frame),
+            TokenType::LeftBracket => self.list(frame),
+            TokenType::LeftBrace => self.map(frame),
+            _ => self.error_at(self.previous.line, "Expect expression."),
         }
     }
 
-    fn infix_rule(&mut self, operator_type: TokenType, frame: &mut Chunk) {
+    fn infix_rule(&mut self, operator_type: TokenType, can_assign: bool, frame: &mut Chunk) {
         match operator_type {
+            TokenType::LeftBracket => self.index(can_assign, frame),
+            TokenType::Dot => self.method(frame),
             TokenType::Minus => self.binary(frame),
             TokenType::Plus => self.binary(frame),
             TokenType::Slash => self.binary(frame),
             TokenType::Star => self.binary(frame),
+            TokenType::Percent => self.binary(frame),
+            TokenType::StarStar => self.binary(frame),
+            TokenType::Ampersand => self.binary(frame),
+            TokenType::Pipe => self.binary(frame),
+            TokenType::Caret => self.binary(frame),
+            TokenType::LessLess => self.binary(frame),
+            TokenType::GreaterGreater => self.binary(frame),
             TokenType::BangEqual => self.binary(frame),
             TokenType::EqualEqual => self.binary(frame),
             TokenType::Greater => self.binary(frame),
             TokenType::GreaterEqual => self.binary(frame),
             TokenType::Less => self.binary(frame),
             TokenType::LessEqual => self.binary(frame),
+            TokenType::And => self.and(frame),
+            TokenType::Or => self.or(frame),
             _ => (), //panic!("Expect expresion"),
         }
     }
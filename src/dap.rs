@@ -0,0 +1,643 @@
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    debugger::{self, DebugAction, DebugHook, Debugger, ExecutionState, PauseReason, StepMode},
+    interpreter::{InterpretError, Interpreter},
+    protocol::Connection,
+    value::Value,
+};
+
+/// Rux runs a single thread, so every thread id in the protocol is this one.
+const THREAD_ID: u64 = 1;
+/// The scopes of a paused frame: the VM stack, the locals in scope and the
+/// globals.
+const STACK_REFERENCE: u64 = 1;
+const LOCALS_REFERENCE: u64 = 2;
+const GLOBALS_REFERENCE: u64 = 3;
+
+/// Serves the Debug Adapter Protocol on stdin and stdout until the client
+/// disconnects. Everything else the adapter has to say goes to stderr.
+pub fn run(interpreter: Interpreter) {
+    let stdin = BufReader::new(io::stdin());
+    if let Err(error) = serve(interpreter, Box::new(stdin), Box::new(io::stdout())) {
+        eprintln!("Debug adapter failed: {error}");
+    }
+}
+
+pub fn serve(
+    mut interpreter: Interpreter,
+    reader: Box<dyn BufRead>,
+    writer: Box<dyn Write>,
+) -> io::Result<()> {
+    let session = Rc::new(RefCell::new(Session::new(Connection::new(reader, writer))));
+
+    while !session.borrow().disconnected {
+        let Some(request) = session.borrow_mut().connection.read()? else {
+            break;
+        };
+        let flow = session.borrow_mut().handle(&request, None)?;
+        if let Flow::Run = flow {
+            run_program(&session, &mut interpreter)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_program(session: &Rc<RefCell<Session>>, interpreter: &mut Interpreter) -> io::Result<()> {
+    let Some(program) = session.borrow().program.clone() else {
+        return Ok(());
+    };
+    if !program.no_debug {
+        interpreter.attach_debugger(Box::new(DapHook(session.clone())));
+    }
+
+    interpreter.set_script_path(Path::new(&program.path));
+    let result = interpreter.interpret(&program.source);
+
+    let mut session = session.borrow_mut();
+    if session.disconnected {
+        return Ok(());
+    }
+    let exit_code = match result {
+        Ok(value) => {
+            session.output("stdout", &format!("{value}\n"))?;
+            0
+        }
+        Err(error) => {
+            session.output("stderr", &format!("{error}\n"))?;
+            match error {
+                InterpretError::Compile => 65,
+                InterpretError::Runtime(_) => 70,
+            }
+        }
+    };
+    session.event("exited", json!({ "exitCode": exit_code }))?;
+    session.event("terminated", json!({}))
+}
+
+#[derive(Debug, Clone)]
+struct Program {
+    path: String,
+    source: String,
+    no_debug: bool,
+}
+
+/// What the caller should do once a request has been answered.
+enum Flow {
+    Wait,
+    Run,
+    Resume(StepMode),
+    Stop,
+}
+
+#[derive(Debug)]
+struct Session {
+    connection: Connection,
+    /// Sequence number of the last message sent.
+    seq: u64,
+    program: Option<Program>,
+    debugger: Option<Debugger>,
+    disconnected: bool,
+}
+
+impl Session {
+    fn new(connection: Connection) -> Self {
+        Session {
+            connection,
+            seq: 0,
+            program: None,
+            debugger: None,
+            disconnected: false,
+        }
+    }
+
+    /// Answers one request. `state` is the paused VM, if any, which is the
+    /// only time requests about frames and values can be answered.
+    fn handle(&mut self, request: &Json, state: Option<&ExecutionState>) -> io::Result<Flow> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        let (result, flow) = match command {
+            "initialize" => (
+                Ok(json!({ "supportsConfigurationDoneRequest": true })),
+                Flow::Wait,
+            ),
+            "launch" => (self.launch(arguments).map(|()| json!({})), Flow::Wait),
+            "setBreakpoints" => (self.set_breakpoints(arguments), Flow::Wait),
+            "setExceptionBreakpoints" => (Ok(json!({})), Flow::Wait),
+            "configurationDone" => match (&self.program, state) {
+                (None, _) => (Err(String::from("No program was launched.")), Flow::Wait),
+                (Some(_), None) => (Ok(json!({})), Flow::Run),
+                (Some(_), Some(_)) => (Ok(json!({})), Flow::Wait),
+            },
+            "threads" => (
+                Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+                Flow::Wait,
+            ),
+            "stackTrace" => (
+                paused(state).map(|state| self.stack_trace(state)),
+                Flow::Wait,
+            ),
+            "scopes" => (
+                paused(state).map(|_| {
+                    let scopes: Vec<Json> = [
+                        ("Locals", LOCALS_REFERENCE),
+                        ("Globals", GLOBALS_REFERENCE),
+                        ("Stack", STACK_REFERENCE),
+                    ]
+                    .iter()
+                    .map(|(name, reference)| {
+                        json!({ "name": name, "variablesReference": reference, "expensive": false })
+                    })
+                    .collect();
+                    json!({ "scopes": scopes })
+                }),
+                Flow::Wait,
+            ),
+            "variables" => (
+                paused(state).map(|state| variables(state, arguments)),
+                Flow::Wait,
+            ),
+            "evaluate" => (
+                paused(state)
+                    .and_then(|state| {
+                        let expression = arguments["expression"].as_str().unwrap_or_default();
+                        debugger::evaluate(expression, state)
+                    })
+                    .map(|result| json!({ "result": result, "variablesReference": 0 })),
+                Flow::Wait,
+            ),
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                let mode = match command {
+                    "continue" => StepMode::Continue,
+                    "next" => StepMode::StepOver,
+                    "stepIn" => StepMode::StepIn,
+                    _ => StepMode::StepOut,
+                };
+                match paused(state) {
+                    Ok(_) => (
+                        Ok(json!({ "allThreadsContinued": true })),
+                        Flow::Resume(mode),
+                    ),
+                    Err(message) => (Err(message), Flow::Wait),
+                }
+            }
+            "disconnect" | "terminate" => {
+                self.disconnected = true;
+                (Ok(json!({})), Flow::Stop)
+            }
+            _ => (Err(format!("Unsupported request '{command}'.")), Flow::Wait),
+        };
+
+        let launched = command == "launch" && result.is_ok();
+        self.respond(request, result)?;
+        // Breakpoints can only be placed once the program is known, so the
+        // client is told to send them after the launch rather than before.
+        if launched {
+            self.event("initialized", json!({}))?;
+        }
+        Ok(flow)
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<(), String> {
+        if self.program.is_some() {
+            return Err(String::from("A program was already launched."));
+        }
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("Launching needs a 'program' to run.")?;
+        let source = fs::read_to_string(path)
+            .map_err(|error| format!("Could not read '{path}': {error}"))?;
+
+        let stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.debugger = Some(Debugger::new(path, stop_on_entry));
+        self.program = Some(Program {
+            path: path.to_string(),
+            source,
+            no_debug: arguments["noDebug"].as_bool().unwrap_or(false),
+        });
+        Ok(())
+    }
+
+    /// Replaces every breakpoint in the given source, as the protocol asks.
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().ok_or("No program was launched.")?;
+        let file = arguments["source"]["path"].as_str().unwrap_or_default();
+        let lines = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64());
+
+        debugger.clear_breakpoints(file);
+        let breakpoints: Vec<Json> = lines
+            .map(|line| {
+                let verified = debugger.set_breakpoint(file, line as u32);
+                json!({ "verified": verified, "line": line })
+            })
+            .collect();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self, state: &ExecutionState) -> Json {
+        let path = self
+            .debugger
+            .as_ref()
+            .map_or_else(String::new, |debugger| debugger.running_file(state));
+        let name = Path::new(&path)
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        json!({
+            "stackFrames": [{
+                "id": 0,
+                "name": "<script>",
+                "source": { "name": name, "path": path },
+                "line": state.line().unwrap_or(0),
+                "column": 1,
+            }],
+            "totalFrames": 1,
+        })
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        self.connection.write(&message)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn output(&mut self, category: &str, output: &str) -> io::Result<()> {
+        self.event("output", json!({ "category": category, "output": output }))
+    }
+
+    /// Pauses the VM if the debugger asks for it, answering requests until
+    /// the client resumes or goes away.
+    fn before_instruction(&mut self, state: &ExecutionState) -> io::Result<DebugAction> {
+        let Some(reason) = self
+            .debugger
+            .as_mut()
+            .and_then(|debugger| debugger.should_pause(state))
+        else {
+            return Ok(DebugAction::Continue);
+        };
+
+        let reason = match reason {
+            PauseReason::Entry => "entry",
+            PauseReason::Breakpoint => "breakpoint",
+            PauseReason::Step => "step",
+        };
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )?;
+
+        while let Some(request) = self.connection.read()? {
+            match self.handle(&request, Some(state))? {
+                Flow::Resume(mode) => {
+                    if let Some(debugger) = self.debugger.as_mut() {
+                        debugger.resume(mode, state);
+                    }
+                    return Ok(DebugAction::Continue);
+                }
+                Flow::Stop => return Ok(DebugAction::Stop),
+                Flow::Wait | Flow::Run => (),
+            }
+        }
+        Ok(DebugAction::Stop)
+    }
+}
+
+fn paused<'a, 'b>(state: Option<&'a ExecutionState<'b>>) -> Result<&'a ExecutionState<'b>, String> {
+    state.ok_or_else(|| String::from("The program is not paused."))
+}
+
+fn variables(state: &ExecutionState, arguments: &Json) -> Json {
+    let variables: Vec<(String, &Value)> = match arguments["variablesReference"].as_u64() {
+        Some(STACK_REFERENCE) => state
+            .stack
+            .contents()
+            .iter()
+            .enumerate()
+            .map(|(slot, value)| (format!("[{slot}]"), value))
+            .collect(),
+        Some(LOCALS_REFERENCE) => named(state.locals()),
+        Some(GLOBALS_REFERENCE) => named(state.globals()),
+        _ => vec![],
+    };
+    let variables: Vec<Json> = variables
+        .into_iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name,
+                "value": value.to_string(),
+                "type": value.type_name(),
+                "variablesReference": 0,
+            })
+        })
+        .collect();
+    json!({ "variables": variables })
+}
+
+fn named<'a>(variables: Vec<(&str, &'a Value)>) -> Vec<(String, &'a Value)> {
+    variables
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
+
+/// Hands every instruction to the session the adapter is serving.
+#[derive(Debug)]
+struct DapHook(Rc<RefCell<Session>>);
+
+impl DebugHook for DapHook {
+    fn before_instruction(&mut self, state: &ExecutionState) -> DebugAction {
+        self.0
+            .borrow_mut()
+            .before_instruction(state)
+            .unwrap_or(DebugAction::Stop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::Cursor, process};
+
+    use serde_json::{json, Value as Json};
+
+    use super::serve;
+    use crate::{
+        interpreter::{Interpreter, Options},
+        protocol::{frame, unframe, SharedBuffer},
+    };
+
+    #[test]
+    fn debugging_session() {
+        let program = env::temp_dir().join(format!("rux-dap-{}.lox", process::id()));
+        fs::write(&program, "1 +\n2 *\n3").unwrap();
+        let path = program.to_str().unwrap();
+
+        let messages = session(&[
+            ("initialize", json!({ "adapterID": "rux" })),
+            ("launch", json!({ "program": path })),
+            (
+                "setBreakpoints",
+                json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }] }),
+            ),
+            ("configurationDone", json!({})),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("variables", json!({ "variablesReference": 1 })),
+            ("evaluate", json!({ "expression": "1 + 2" })),
+            ("next", json!({ "threadId": 1 })),
+            ("variables", json!({ "variablesReference": 1 })),
+            ("continue", json!({ "threadId": 1 })),
+            ("disconnect", json!({})),
+        ]);
+        fs::remove_file(&program).unwrap();
+
+        assert!(responses(&messages).all(|response| response["success"] == true));
+        assert_eq!(
+            events(&messages),
+            vec![
+                "initialized",
+                "stopped",
+                "stopped",
+                "output",
+                "exited",
+                "terminated"
+            ]
+        );
+
+        let body = |command: &str, nth: usize| {
+            responses(&messages)
+                .filter(|response| response["command"] == command)
+                .nth(nth)
+                .unwrap()["body"]
+                .clone()
+        };
+        assert_eq!(
+            body("setBreakpoints", 0)["breakpoints"][0]["verified"],
+            true
+        );
+        assert_eq!(body("stackTrace", 0)["stackFrames"][0]["line"], 2);
+        assert_eq!(body("variables", 0)["variables"][0]["value"], "1");
+        assert_eq!(body("variables", 1)["variables"][1]["value"], "2");
+        assert_eq!(body("evaluate", 0)["result"], "3");
+
+        let stopped: Vec<&Json> = messages
+            .iter()
+            .filter(|message| message["event"] == "stopped")
+            .map(|message| &message["body"]["reason"])
+            .collect();
+        assert_eq!(stopped, vec!["breakpoint", "step"]);
+        let output = messages.iter().find(|message| message["event"] == "output");
+        assert_eq!(output.unwrap()["body"]["output"], "7\n");
+    }
+
+    #[test]
+    fn variable_scopes() {
+        let program = env::temp_dir().join(format!("rux-dap-scopes-{}.lox", process::id()));
+        fs::write(&program, "var g = 1;\n{ var a = [2];\n  g + a[0]; }").unwrap();
+        let path = program.to_str().unwrap();
+
+        let messages = session(&[
+            ("launch", json!({ "program": path })),
+            (
+                "setBreakpoints",
+                json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }] }),
+            ),
+            ("configurationDone", json!({})),
+            ("scopes", json!({ "frameId": 0 })),
+            ("variables", json!({ "variablesReference": 2 })),
+            ("variables", json!({ "variablesReference": 3 })),
+            ("evaluate", json!({ "expression": "g + a[0]" })),
+            ("continue", json!({ "threadId": 1 })),
+        ]);
+        fs::remove_file(&program).unwrap();
+
+        assert!(responses(&messages).all(|response| response["success"] == true));
+        let body = |command: &str, nth: usize| {
+            responses(&messages)
+                .filter(|response| response["command"] == command)
+                .nth(nth)
+                .unwrap()["body"]
+                .clone()
+        };
+        let scopes: Vec<Json> = body("scopes", 0)["scopes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|scope| scope["name"].clone())
+            .collect();
+        assert_eq!(scopes, ["Locals", "Globals", "Stack"]);
+        assert_eq!(
+            body("variables", 0)["variables"],
+            json!([{ "name": "a", "value": "[2]", "type": "list", "variablesReference": 0 }])
+        );
+        assert_eq!(body("variables", 1)["variables"][0]["name"], "g");
+        assert_eq!(body("evaluate", 0)["result"], "3");
+    }
+
+    #[test]
+    fn relative_program_path() {
+        // Tests run from the package root, so this path is relative to it.
+        let path = format!("target/rux-dap-relative-{}.lox", process::id());
+        fs::write(&path, "1 +\n2 *\n3").unwrap();
+        let full_path = fs::canonicalize(&path).unwrap();
+
+        let messages = session(&[
+            ("launch", json!({ "program": path })),
+            (
+                "setBreakpoints",
+                json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }] }),
+            ),
+            ("configurationDone", json!({})),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("continue", json!({ "threadId": 1 })),
+        ]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(responses(&messages).all(|response| response["success"] == true));
+        let stopped = messages
+            .iter()
+            .find(|message| message["event"] == "stopped")
+            .unwrap();
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+        let frame = &responses(&messages)
+            .find(|response| response["command"] == "stackTrace")
+            .unwrap()["body"]["stackFrames"][0];
+        assert_eq!(frame["line"], 2);
+        assert_eq!(frame["source"]["path"], full_path.to_str().unwrap());
+    }
+
+    #[test]
+    fn imported_modules() {
+        let directory = env::temp_dir().join(format!("rux-dap-imports-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let module = directory.join("lib.lox");
+        fs::write(&module, "var x = 1;\nvar y = 2;").unwrap();
+        // Modules run under their full path.
+        let module = fs::canonicalize(module).unwrap();
+        let program = directory.join("main.lox");
+        fs::write(&program, "import \"lib.lox\" as lib;\nlib[\"y\"]").unwrap();
+        let path = program.to_str().unwrap();
+
+        let messages = session(&[
+            ("launch", json!({ "program": path, "stopOnEntry": true })),
+            (
+                "setBreakpoints",
+                json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }] }),
+            ),
+            ("configurationDone", json!({})),
+            ("stepIn", json!({ "threadId": 1 })),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("continue", json!({ "threadId": 1 })),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("continue", json!({ "threadId": 1 })),
+        ]);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(responses(&messages).all(|response| response["success"] == true));
+        let stopped: Vec<&Json> = messages
+            .iter()
+            .filter(|message| message["event"] == "stopped")
+            .map(|message| &message["body"]["reason"])
+            .collect();
+        assert_eq!(stopped, vec!["entry", "step", "breakpoint"]);
+
+        let frames: Vec<Json> = responses(&messages)
+            .filter(|response| response["command"] == "stackTrace")
+            .map(|response| response["body"]["stackFrames"][0].clone())
+            .collect();
+        assert_eq!(frames[0]["source"]["path"], module.to_str().unwrap());
+        assert_eq!(frames[0]["source"]["name"], "lib.lox");
+        assert_eq!(frames[0]["line"], 1);
+        assert_eq!(frames[1]["source"]["path"], path);
+        assert_eq!(frames[1]["line"], 2);
+    }
+
+    #[test]
+    fn failed_requests() {
+        let messages = session(&[
+            ("launch", json!({ "program": "does/not/exist.lox" })),
+            ("configurationDone", json!({})),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("continue", json!({ "threadId": 1 })),
+            ("evaluate", json!({ "expression": "-\"a\"" })),
+            ("restartFrame", json!({})),
+        ]);
+
+        assert_eq!(responses(&messages).count(), 6);
+        assert!(responses(&messages).all(|response| response["success"] == false));
+        assert!(events(&messages).is_empty());
+    }
+
+    /// Plays the requests to a debug adapter and returns everything it sent.
+    fn session(requests: &[(&str, Json)]) -> Vec<Json> {
+        let requests: Vec<Json> = requests
+            .iter()
+            .enumerate()
+            .map(|(seq, (command, arguments))| {
+                json!({
+                    "seq": seq + 1,
+                    "type": "request",
+                    "command": command,
+                    "arguments": arguments,
+                })
+            })
+            .collect();
+
+        let output = SharedBuffer::default();
+        let interpreter = Interpreter::with_options(Options {
+            optimize: false,
+            ..Options::default()
+        });
+        serve(
+            interpreter,
+            Box::new(Cursor::new(frame(&requests))),
+            Box::new(output.clone()),
+        )
+        .unwrap();
+
+        let output = output.0.borrow().clone();
+        unframe(output)
+    }
+
+    fn responses(messages: &[Json]) -> impl Iterator<Item = &Json> {
+        messages
+            .iter()
+            .filter(|message| message["type"] == "response")
+    }
+
+    fn events(messages: &[Json]) -> Vec<&str> {
+        messages
+            .iter()
+            .filter_map(|message| message["event"].as_str())
+            .collect()
+    }
+}
@@ -0,0 +1,588 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Debug,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    chunk::Chunk,
+    interpreter::{Interpreter, Options},
+    stack::Stack,
+    value::Value,
+};
+
+/// What the VM should do once a hook is done with an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugAction {
+    Continue,
+    Stop,
+}
+
+/// The VM as it is right before an instruction runs.
+pub struct ExecutionState<'a> {
+    pub chunk: &'a Chunk,
+    pub ip: usize,
+    pub stack: &'a Stack,
+    pub globals: &'a HashMap<String, Value>,
+    /// The file the running code was read from, if known. Imported modules
+    /// run with their own file.
+    pub file: Option<&'a Path>,
+    /// Number of active call frames, the script itself being the first.
+    pub depth: usize,
+}
+
+impl ExecutionState<'_> {
+    pub fn line(&self) -> Option<u32> {
+        self.chunk.line(self.ip)
+    }
+
+    /// The named locals in scope, outermost first.
+    pub fn locals(&self) -> Vec<(&str, &Value)> {
+        let mut locals: Vec<_> = self
+            .chunk
+            .locals
+            .iter()
+            .filter(|local| (local.start..local.end).contains(&self.ip))
+            .filter_map(|local| {
+                let value = self.stack.contents().get(local.slot)?;
+                Some((local.slot, local.name.as_str(), value))
+            })
+            .collect();
+        locals.sort_by_key(|(slot, ..)| *slot);
+        locals
+            .into_iter()
+            .map(|(_, name, value)| (name, value))
+            .collect()
+    }
+
+    /// The globals, sorted by name.
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        globals.sort_by_key(|(name, _)| *name);
+        globals
+    }
+}
+
+/// Called by the VM before every instruction once attached with
+/// `Interpreter::attach_debugger`.
+pub trait DebugHook: Debug {
+    fn before_instruction(&mut self, state: &ExecutionState) -> DebugAction;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    Continue,
+    StepIn,
+    StepOver,
+    StepOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseReason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+/// Breakpoints and stepping over the chunk's line table, shared by every
+/// debugger front end.
+#[derive(Debug)]
+pub struct Debugger {
+    file: String,
+    breakpoints: BTreeSet<u32>,
+    mode: StepMode,
+    stop_on_entry: bool,
+    started: bool,
+    /// Call depth when execution last resumed, for stepping over and out.
+    resumed_at_depth: usize,
+    last_line: Option<u32>,
+    last_file: Option<PathBuf>,
+}
+
+impl Debugger {
+    /// The file is kept as its canonical path, which is how the VM names
+    /// the file it runs, or as given if it can't be resolved.
+    pub fn new(file: &str, stop_on_entry: bool) -> Self {
+        let file = fs::canonicalize(file)
+            .map_or_else(|_| file.to_string(), |path| path.display().to_string());
+        Debugger {
+            file,
+            breakpoints: BTreeSet::new(),
+            mode: StepMode::Continue,
+            stop_on_entry,
+            started: false,
+            resumed_at_depth: 0,
+            last_line: None,
+            last_file: None,
+        }
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Whether `file` names the file being debugged, either by a path to it
+    /// or by its trailing components, such as `main.lox` for `src/main.lox`.
+    pub fn is_debugged_file(&self, file: &str) -> bool {
+        Path::new(&self.file).ends_with(file)
+            || fs::canonicalize(file).is_ok_and(|file| file == Path::new(&self.file))
+    }
+
+    /// Whether `state` is running the file being debugged rather than a
+    /// module it imported.
+    pub fn is_running_debugged_file(&self, state: &ExecutionState) -> bool {
+        state.file.is_none_or(|file| file == Path::new(&self.file))
+    }
+
+    /// The file `state` is running, for showing where execution is.
+    pub fn running_file(&self, state: &ExecutionState) -> String {
+        state
+            .file
+            .map_or_else(|| self.file.clone(), |file| file.display().to_string())
+    }
+
+    /// Only the file being debugged can hold breakpoints, so this returns
+    /// false for any other file.
+    pub fn set_breakpoint(&mut self, file: &str, line: u32) -> bool {
+        let is_debugged_file = self.is_debugged_file(file);
+        if is_debugged_file {
+            self.breakpoints.insert(line);
+        }
+        is_debugged_file
+    }
+
+    pub fn clear_breakpoint(&mut self, file: &str, line: u32) -> bool {
+        self.is_debugged_file(file) && self.breakpoints.remove(&line)
+    }
+
+    pub fn clear_breakpoints(&mut self, file: &str) {
+        if self.is_debugged_file(file) {
+            self.breakpoints.clear();
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u32> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn resume(&mut self, mode: StepMode, state: &ExecutionState) {
+        self.mode = mode;
+        self.resumed_at_depth = state.depth;
+    }
+
+    /// Decides whether to pause before the instruction in `state`. Stepping
+    /// and breakpoints pause on the first instruction of a new source line.
+    pub fn should_pause(&mut self, state: &ExecutionState) -> Option<PauseReason> {
+        let line = state.line();
+        let new_file = state.file != self.last_file.as_deref();
+        if new_file {
+            self.last_file = state.file.map(Path::to_path_buf);
+        }
+        let new_line = new_file || line != self.last_line;
+        self.last_line = line;
+
+        if !self.started {
+            self.started = true;
+            if self.stop_on_entry {
+                return Some(PauseReason::Entry);
+            }
+        }
+
+        if new_line
+            && self.is_running_debugged_file(state)
+            && line.is_some_and(|line| self.breakpoints.contains(&line))
+        {
+            return Some(PauseReason::Breakpoint);
+        }
+
+        let step_done = match self.mode {
+            StepMode::Continue => false,
+            StepMode::StepIn => new_line,
+            StepMode::StepOver => new_line && state.depth <= self.resumed_at_depth,
+            StepMode::StepOut => state.depth < self.resumed_at_depth,
+        };
+        step_done.then_some(PauseReason::Step)
+    }
+}
+
+/// Evaluates an expression for a paused frame. It runs in a VM of its own
+/// whose globals are the frame's globals and locals in scope, so it can read
+/// them, but assigning to one doesn't change the program's.
+pub fn evaluate(expression: &str, state: &ExecutionState) -> Result<String, String> {
+    let mut interpreter = Interpreter::with_options(Options::default());
+    // Locals come last to shadow globals, and inner ones outer ones.
+    for (name, value) in state.globals().into_iter().chain(state.locals()) {
+        interpreter.define_global(name, value.clone());
+    }
+    interpreter
+        .interpret(expression)
+        .map(|value| value.to_string())
+        .map_err(|error| error.message())
+}
+
+const HELP: &str = "\
+c, continue         run until the next breakpoint
+s, step             step into the next line
+n, next             step over the next line
+o, out              run until the current frame returns
+b, break [file:]N   set a breakpoint on line N
+d, delete [file:]N  remove the breakpoint on line N
+breakpoints         list breakpoints
+bt, backtrace       show the call frames
+stack               show the values on the stack
+locals              show the local variables in scope
+globals             show the global variables
+p, print <expr>     evaluate an expression
+l, list             show the source around the current line
+q, quit             stop the program
+h, help             show this message";
+
+/// Debugger front end reading commands from stdin.
+#[derive(Debug)]
+pub struct TerminalDebugger {
+    debugger: Debugger,
+    source: Vec<String>,
+}
+
+impl TerminalDebugger {
+    pub fn new(file: &str, source: &str) -> Self {
+        TerminalDebugger {
+            debugger: Debugger::new(file, true),
+            source: source.lines().map(String::from).collect(),
+        }
+    }
+
+    fn show_location(&self, state: &ExecutionState, reason: PauseReason) {
+        let line = state.line().unwrap_or(0);
+        let reason = match reason {
+            PauseReason::Entry => "Paused on entry",
+            PauseReason::Breakpoint => "Breakpoint",
+            PauseReason::Step => "Step",
+        };
+        println!(
+            "{} at {}:{}",
+            reason,
+            self.debugger.running_file(state),
+            line
+        );
+        self.list(state, line, 0);
+    }
+
+    /// Prints the lines around `line` of the file `state` is running.
+    fn list(&self, state: &ExecutionState, line: u32, context: u32) {
+        let module: Vec<String>;
+        let source = match state.file {
+            Some(file) if !self.debugger.is_running_debugged_file(state) => {
+                let text = fs::read_to_string(file).unwrap_or_default();
+                module = text.lines().map(String::from).collect();
+                &module
+            }
+            _ => &self.source,
+        };
+        let first = line.saturating_sub(context).max(1);
+        for number in first..=line + context {
+            if let Some(text) = source.get(number as usize - 1) {
+                let marker = if number == line { "->" } else { "  " };
+                println!("{marker} {number:4} {text}");
+            }
+        }
+    }
+
+    fn print_variables(variables: &[(&str, &Value)]) {
+        if variables.is_empty() {
+            println!("None.");
+        }
+        for (name, value) in variables {
+            println!("{name} = {value}");
+        }
+    }
+
+    /// Parses `file:line` or a bare line in the file being debugged.
+    fn location(&self, argument: &str) -> Option<(String, u32)> {
+        match argument.rsplit_once(':') {
+            Some((file, line)) => Some((file.to_string(), line.parse().ok()?)),
+            None => Some((self.debugger.file().to_string(), argument.parse().ok()?)),
+        }
+    }
+
+    /// Handles one command, returning how to resume if the command does.
+    fn command(&mut self, state: &ExecutionState, input: &str) -> Option<StepMode> {
+        let (name, argument) = match input.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (input, ""),
+        };
+
+        match name {
+            "c" | "continue" => return Some(StepMode::Continue),
+            "s" | "step" => return Some(StepMode::StepIn),
+            "n" | "next" => return Some(StepMode::StepOver),
+            "o" | "out" => return Some(StepMode::StepOut),
+            "b" | "break" => match self.location(argument) {
+                Some((file, line)) if self.debugger.set_breakpoint(&file, line) => {
+                    println!("Breakpoint set at {file}:{line}")
+                }
+                Some((file, _)) => println!("Can't set breakpoints in '{file}'"),
+                None => println!("Expected a line like 'break 3' or 'break file.lox:3'"),
+            },
+            "d" | "delete" => match self.location(argument) {
+                Some((file, line)) if self.debugger.clear_breakpoint(&file, line) => {
+                    println!("Breakpoint removed from {file}:{line}")
+                }
+                Some((file, line)) => println!("No breakpoint at {file}:{line}"),
+                None => println!("Expected a line like 'delete 3' or 'delete file.lox:3'"),
+            },
+            "breakpoints" => {
+                for line in self.debugger.breakpoints() {
+                    println!("{}:{}", self.debugger.file(), line);
+                }
+            }
+            "bt" | "backtrace" => println!(
+                "#0 <script> at {}:{}",
+                self.debugger.running_file(state),
+                state.line().unwrap_or(0)
+            ),
+            "stack" => print!("{}", state.stack),
+            "locals" => Self::print_variables(&state.locals()),
+            "globals" => Self::print_variables(&state.globals()),
+            "p" | "print" => match evaluate(argument, state) {
+                Ok(value) => println!("{value}"),
+                Err(error) => println!("{error}"),
+            },
+            "l" | "list" => self.list(state, state.line().unwrap_or(0), 5),
+            "h" | "help" => println!("{HELP}"),
+            "" => (),
+            _ => println!("Unknown command '{name}'. Try 'help'."),
+        }
+        None
+    }
+}
+
+impl DebugHook for TerminalDebugger {
+    fn before_instruction(&mut self, state: &ExecutionState) -> DebugAction {
+        let Some(reason) = self.debugger.should_pause(state) else {
+            return DebugAction::Continue;
+        };
+        self.show_location(state, reason);
+
+        let stdin = io::stdin();
+        loop {
+            print!("(rux-debug) ");
+            io::stdout().flush().expect("Failed to flush to stdout");
+
+            let mut input = String::new();
+            match stdin.lock().read_line(&mut input) {
+                Ok(0) | Err(_) => return DebugAction::Stop,
+                Ok(_) => (),
+            }
+
+            let input = input.trim();
+            if input == "q" || input == "quit" {
+                return DebugAction::Stop;
+            }
+            if let Some(mode) = self.command(state, input) {
+                self.debugger.resume(mode, state);
+                return DebugAction::Continue;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, env, fs, process, rc::Rc};
+
+    use super::{
+        evaluate, DebugAction, DebugHook, Debugger, ExecutionState, PauseReason, StepMode,
+    };
+    use crate::{compiler::Compiler, vm::VM};
+
+    /// Resumes with the queued modes in order and records every pause.
+    #[derive(Debug)]
+    struct ScriptedHook {
+        debugger: Debugger,
+        modes: VecDeque<StepMode>,
+        pauses: Rc<RefCell<Vec<(PauseReason, u32)>>>,
+    }
+
+    impl DebugHook for ScriptedHook {
+        fn before_instruction(&mut self, state: &ExecutionState) -> DebugAction {
+            match self.debugger.should_pause(state) {
+                Some(reason) => {
+                    self.pauses
+                        .borrow_mut()
+                        .push((reason, state.line().unwrap()));
+                    match self.modes.pop_front() {
+                        Some(mode) => {
+                            self.debugger.resume(mode, state);
+                            DebugAction::Continue
+                        }
+                        None => DebugAction::Stop,
+                    }
+                }
+                None => DebugAction::Continue,
+            }
+        }
+    }
+
+    const SOURCE: &str = "1 +\n2 *\n3 -\n4";
+
+    #[test]
+    fn stepping() {
+        let pauses = run(Debugger::new("test.lox", true), &[StepMode::StepIn; 4]);
+        assert_eq!(
+            pauses,
+            vec![
+                (PauseReason::Entry, 1),
+                (PauseReason::Step, 2),
+                (PauseReason::Step, 3),
+                (PauseReason::Step, 4),
+            ]
+        );
+
+        let pauses = run(
+            Debugger::new("test.lox", true),
+            &[StepMode::StepOver, StepMode::StepOut],
+        );
+        assert_eq!(
+            pauses,
+            vec![(PauseReason::Entry, 1), (PauseReason::Step, 2)]
+        );
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = Debugger::new("scripts/test.lox", false);
+        assert!(debugger.set_breakpoint("test.lox", 3));
+        assert!(!debugger.set_breakpoint("other.lox", 2));
+        assert!(!debugger.set_breakpoint("st.lox", 2));
+        let pauses = run(debugger, &[StepMode::Continue]);
+        assert_eq!(pauses, vec![(PauseReason::Breakpoint, 3)]);
+
+        let mut debugger = Debugger::new("test.lox", false);
+        debugger.set_breakpoint("test.lox", 2);
+        debugger.set_breakpoint("test.lox", 4);
+        assert!(debugger.clear_breakpoint("test.lox", 2));
+        assert!(!debugger.clear_breakpoint("test.lox", 2));
+        let pauses = run(debugger, &[StepMode::Continue]);
+        assert_eq!(pauses, vec![(PauseReason::Breakpoint, 4)]);
+    }
+
+    #[test]
+    fn breakpoints_in_imports() {
+        let directory = env::temp_dir().join(format!("rux-debug-imports-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("lib.lox"), "var x = 1;\nvar y = 2;").unwrap();
+        let main = directory.join("main.lox");
+        let source = "import \"lib.lox\" as lib;\nlib[\"y\"]";
+        fs::write(&main, source).unwrap();
+        let path = main.to_str().unwrap();
+
+        let mut debugger = Debugger::new(path, false);
+        debugger.set_breakpoint("main.lox", 2);
+        let mut compiler = Compiler::from_source(source);
+        compiler.optimize = false;
+        let chunk = compiler.compile();
+        let pauses = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::new();
+        vm.path = Some(main.clone());
+        vm.debugger = Some(Box::new(ScriptedHook {
+            debugger,
+            modes: VecDeque::from([StepMode::Continue]),
+            pauses: pauses.clone(),
+        }));
+        let result = vm.run_main(&chunk);
+        fs::remove_dir_all(&directory).unwrap();
+
+        // Line 2 of the module doesn't hold the breakpoint.
+        assert_eq!(result.unwrap().to_string(), "2");
+        assert_eq!(*pauses.borrow(), vec![(PauseReason::Breakpoint, 2)]);
+    }
+
+    /// A line, the locals in scope there and what the expression evaluated to.
+    type Inspection = (u32, Vec<String>, Result<String, String>);
+
+    /// Evaluates an expression before the first instruction of every line
+    /// and records the line, the locals in scope and the result.
+    #[derive(Debug)]
+    struct Inspector {
+        expression: &'static str,
+        last_line: Option<u32>,
+        seen: Rc<RefCell<Vec<Inspection>>>,
+    }
+
+    impl DebugHook for Inspector {
+        fn before_instruction(&mut self, state: &ExecutionState) -> DebugAction {
+            if state.line() != self.last_line {
+                self.last_line = state.line();
+                let locals = state
+                    .locals()
+                    .iter()
+                    .map(|(name, value)| format!("{name} = {value}"))
+                    .collect();
+                let result = evaluate(self.expression, state);
+                self.seen
+                    .borrow_mut()
+                    .push((state.line().unwrap(), locals, result));
+            }
+            DebugAction::Continue
+        }
+    }
+
+    #[test]
+    fn evaluating() {
+        let source =
+            "var g = 10;\n{ var a = 1;\n  { var b = a + 1; var a = 5;\n    g + a + b; } }\ng";
+        let seen = inspect(source, "g + a + b");
+        assert_eq!(seen.len(), 5);
+        assert_eq!(seen[3].0, 4);
+        assert_eq!(seen[3].1, ["a = 1", "b = 2", "a = 5"]);
+        assert_eq!(seen[3].2, Ok(String::from("17")));
+        assert!(seen[4].1.is_empty());
+        assert_eq!(seen[4].2, Err(String::from("Undefined variable 'a'.")));
+
+        let seen = inspect(source, "g = -\"a\"");
+        assert!(seen[3].2.is_err());
+        // Assignments only change the evaluating VM's copy.
+        let seen = inspect(source, "g = g + 1");
+        assert_eq!(seen[4].2, Ok(String::from("11")));
+    }
+
+    fn inspect(source: &str, expression: &'static str) -> Vec<Inspection> {
+        let chunk = Compiler::from_source(source).compile();
+        let seen = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::new();
+        vm.debugger = Some(Box::new(Inspector {
+            expression,
+            last_line: None,
+            seen: seen.clone(),
+        }));
+        assert_eq!(vm.run(&chunk).unwrap().to_string(), "10");
+
+        let seen = seen.borrow().clone();
+        seen
+    }
+
+    fn run(debugger: Debugger, modes: &[StepMode]) -> Vec<(PauseReason, u32)> {
+        let mut compiler = Compiler::from_source(SOURCE);
+        compiler.optimize = false;
+        let chunk = compiler.compile();
+
+        let pauses = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::new();
+        vm.debugger = Some(Box::new(ScriptedHook {
+            debugger,
+            modes: modes.iter().copied().collect(),
+            pauses: pauses.clone(),
+        }));
+        let _ = vm.run(&chunk);
+
+        let pauses = pauses.borrow().clone();
+        pauses
+    }
+}
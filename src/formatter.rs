@@ -0,0 +1,428 @@
+use crate::{scanner::Scanner, token::TokenType};
+
+const INDENT: &str = "  ";
+
+/// Re-prints `source` in the canonical style: two-space indentation, opening
+/// braces on the line that starts the block, one statement per line and
+/// single spaces around binary operators. Comments are kept, and so is a
+/// single blank line wherever the source had one or more. Statements the
+/// source wraps over several lines keep their line breaks, with the lines
+/// after the first indented once more.
+///
+/// Source the scanner can't make sense of is left alone and reported.
+pub fn format(source: &str) -> Result<String, String> {
+    let tokens = tokens(source)?;
+    let mut printer = Printer::default();
+    for (index, token) in tokens.iter().enumerate() {
+        printer.print(token, tokens.get(index + 1));
+    }
+    Ok(printer.finish())
+}
+
+struct Token<'a> {
+    token_type: TokenType,
+    text: &'a str,
+    /// Lines the token starts and ends on, which differ for multi-line
+    /// strings.
+    first_line: i32,
+    last_line: i32,
+}
+
+fn tokens(source: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = vec![];
+    for token in Scanner::with_comments(source).tokenize() {
+        let data = match (&token.data, token.token_type) {
+            (_, TokenType::Eof) => break,
+            (Ok(data), _) => data,
+            (Err(message), _) => return Err(format!("[line {}] {}", token.line, message)),
+        };
+        let text = data.lexeme;
+        tokens.push(Token {
+            token_type: token.token_type,
+            text,
+            first_line: token.line,
+            last_line: token.line + text.matches('\n').count() as i32,
+        });
+    }
+    Ok(tokens)
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    parens: usize,
+    /// The last token printed, comments aside.
+    previous: Option<TokenType>,
+    previous_unary: bool,
+    /// Line the last token printed, comments included, ended on.
+    previous_line: i32,
+    newline_pending: bool,
+    /// Set after a block comment, which is always followed by a space.
+    space_pending: bool,
+    /// Set after a block comment that starts a line, which keeps the code
+    /// after it on that line.
+    leading_comment: bool,
+    /// Whether the last token printed closed a map literal rather than a
+    /// block, so a `[` after it indexes the map.
+    closed_map: bool,
+    /// For each open brace, whether it started a map literal rather than a
+    /// block. Maps are printed inline, like lists.
+    maps: Vec<bool>,
+}
+
+impl Printer {
+    fn print(&mut self, token: &Token, next: Option<&Token>) {
+        if token.token_type == TokenType::Comment {
+            self.comment(token);
+            return;
+        }
+
+        let opens_map = token.token_type == TokenType::LeftBrace && self.opens_map();
+        let closes_map =
+            token.token_type == TokenType::RightBrace && self.maps.last() == Some(&true);
+        if token.token_type == TokenType::RightBrace && !closes_map {
+            self.indent = self.indent.saturating_sub(1);
+            self.newline_pending = self.previous != Some(TokenType::LeftBrace);
+        }
+        let continues_block = self.previous == Some(TokenType::RightBrace)
+            && matches!(
+                token.token_type,
+                TokenType::Else
+                    | TokenType::Catch
+                    | TokenType::Finally
+                    | TokenType::Semicolon
+                    | TokenType::RightParen
+                    | TokenType::Comma
+            );
+
+        let wrapped = !self.out.is_empty()
+            && token.first_line > self.previous_line
+            && !matches!(
+                token.token_type,
+                TokenType::LeftBrace | TokenType::Semicolon
+            );
+        // The rest of a string after an interpolated expression.
+        let continues_string = token.text.starts_with('}')
+            && matches!(
+                token.token_type,
+                TokenType::String | TokenType::Interpolation
+            );
+        let after_leading_comment = self.leading_comment
+            && token.first_line == self.previous_line
+            && token.token_type != TokenType::RightBrace;
+        if continues_string {
+        } else if after_leading_comment {
+            self.out.push(' ');
+        } else if continues_block {
+            if self.space_before(token.token_type) {
+                self.out.push(' ');
+            }
+        } else if self.newline_pending {
+            self.newline(token, self.indent);
+        } else if wrapped {
+            // Only code that follows other code is a continuation.
+            let indent = self.indent + usize::from(self.previous.is_some() && !closes_map);
+            self.newline(token, indent);
+        } else if self.space_pending || self.space_before(token.token_type) {
+            self.out.push(' ');
+        }
+        self.newline_pending = false;
+        self.space_pending = false;
+        self.leading_comment = false;
+        self.out.push_str(token.text.trim_end());
+        self.closed_map = closes_map;
+
+        self.previous_unary = self.is_unary(token.token_type);
+        self.previous = Some(token.token_type);
+        self.previous_line = token.last_line;
+
+        match token.token_type {
+            TokenType::LeftParen => self.parens += 1,
+            TokenType::RightParen => self.parens = self.parens.saturating_sub(1),
+            TokenType::LeftBrace => {
+                self.maps.push(opens_map);
+                if !opens_map {
+                    self.indent += 1;
+                    self.newline_pending =
+                        next.is_some_and(|next| next.token_type != TokenType::RightBrace);
+                }
+            }
+            TokenType::RightBrace => {
+                self.maps.pop();
+                self.newline_pending = !closes_map;
+            }
+            TokenType::Semicolon if self.parens == 0 => self.newline_pending = true,
+            _ => (),
+        }
+    }
+
+    /// Comments that shared a line with code stay on that line, the rest go
+    /// on lines of their own. Code can carry on after a block comment on the
+    /// same line, but never after a line comment.
+    fn comment(&mut self, comment: &Token) {
+        self.leading_comment = comment.text.starts_with("/*")
+            && (self.out.is_empty() || comment.first_line > self.previous_line);
+        let trailing = !self.out.is_empty() && comment.first_line == self.previous_line;
+        if trailing {
+            self.out.push(' ');
+        } else if !self.out.is_empty() {
+            self.newline(comment, self.indent);
+        }
+        self.out.push_str(comment.text.trim_end());
+        self.previous_line = comment.last_line;
+        if comment.text.starts_with("/*") {
+            self.space_pending = true;
+        } else {
+            self.newline_pending = true;
+        }
+    }
+
+    /// Starts a new line for `token`, keeping one blank line if the source
+    /// had any, except right inside braces.
+    fn newline(&mut self, token: &Token, indent: usize) {
+        if self.out.is_empty() {
+            return;
+        }
+        self.out.push('\n');
+        let blank_line = token.first_line > self.previous_line + 1
+            && token.token_type != TokenType::RightBrace
+            && self.previous != Some(TokenType::LeftBrace);
+        if blank_line {
+            self.out.push('\n');
+        }
+        self.out.push_str(&INDENT.repeat(indent));
+    }
+
+    fn space_before(&self, token_type: TokenType) -> bool {
+        let Some(previous) = self.previous else {
+            return false;
+        };
+        if self.out.ends_with(' ') || self.out.ends_with('\n') || self.out.is_empty() {
+            return false;
+        }
+        match (previous, token_type) {
+            (
+                _,
+                TokenType::RightParen
+                | TokenType::RightBracket
+                | TokenType::Comma
+                | TokenType::Semicolon
+                | TokenType::Dot,
+            ) => false,
+            (
+                TokenType::LeftParen
+                | TokenType::LeftBracket
+                | TokenType::Dot
+                | TokenType::Bang
+                | TokenType::Interpolation,
+                _,
+            ) => false,
+            (TokenType::LeftBrace, TokenType::RightBrace) => false,
+            (_, TokenType::Colon) => false,
+            (TokenType::DotDot | TokenType::DotDotEqual, _)
+            | (_, TokenType::DotDot | TokenType::DotDotEqual) => false,
+            (TokenType::LeftBrace, _) | (_, TokenType::RightBrace)
+                if self.maps.last() == Some(&true) =>
+            {
+                false
+            }
+            (TokenType::Minus, _) if self.previous_unary => false,
+            // Calls and method declarations, as opposed to `if (`.
+            (
+                TokenType::Identifier | TokenType::RightParen | TokenType::This | TokenType::Super,
+                TokenType::LeftParen,
+            ) => false,
+            (TokenType::RightBrace, TokenType::LeftBracket) if self.closed_map => false,
+            // Indexing, as opposed to a list literal.
+            (
+                TokenType::Identifier
+                | TokenType::RightParen
+                | TokenType::RightBracket
+                | TokenType::String
+                | TokenType::This,
+                TokenType::LeftBracket,
+            ) => false,
+            _ => true,
+        }
+    }
+
+    /// Whether a `{` about to be printed starts a map literal, which can only
+    /// be where an expression is expected, rather than a block.
+    fn opens_map(&self) -> bool {
+        !matches!(
+            self.previous,
+            None | Some(
+                TokenType::Semicolon
+                    | TokenType::LeftBrace
+                    | TokenType::RightBrace
+                    | TokenType::RightParen
+                    | TokenType::Else
+                    | TokenType::Try
+                    | TokenType::Finally
+                    | TokenType::EqualGreater
+                    | TokenType::Identifier
+            )
+        )
+    }
+
+    /// Whether a `-` or `!` about to be printed negates what follows rather
+    /// than subtracting from what came before.
+    fn is_unary(&self, token_type: TokenType) -> bool {
+        match token_type {
+            TokenType::Bang => true,
+            TokenType::Minus => !matches!(
+                self.previous,
+                Some(
+                    TokenType::Identifier
+                        | TokenType::Number
+                        | TokenType::String
+                        | TokenType::RightParen
+                        | TokenType::RightBracket
+                        | TokenType::True
+                        | TokenType::False
+                        | TokenType::Nil
+                        | TokenType::This
+                )
+            ),
+            _ => false,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        let trimmed = self.out.trim_end().len();
+        self.out.truncate(trimmed);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+
+    #[test]
+    fn spacing() {
+        assert_formats("1+2*-3", "1 + 2 * -3\n");
+        assert_formats("( 1 -  - 2 )/!true", "(1 - -2) / !true\n");
+        assert_formats("print add( a,b ) ;", "print add(a, b);\n");
+        assert_formats("a.b . c(  )", "a.b.c()\n");
+        assert_formats(
+            "print [ 1,[2] ] [0] [ -1 ]-1",
+            "print [1, [2]][0][-1] - 1\n",
+        );
+        assert_formats("return -x", "return -x\n");
+        assert_formats("x+=2**-y%3", "x += 2 ** -y % 3\n");
+        assert_formats("1 +\n2 *\n      3", "1 +\n  2 *\n  3\n");
+    }
+
+    #[test]
+    fn blocks() {
+        assert_formats(
+            "fun f(a){if(a>1){return a;}else{return -a;}}\nvar x=f(2);",
+            "\
+fun f(a) {
+  if (a > 1) {
+    return a;
+  } else {
+    return -a;
+  }
+}
+var x = f(2);
+",
+        );
+        assert_formats(
+            "for(var i=0;i<3;i=i+1)\n{\n\n\nprint i;\n\n\nprint i;\n}",
+            "for (var i = 0; i < 3; i = i + 1) {\n  print i;\n\n  print i;\n}\n",
+        );
+        assert_formats("class A{init(){ }}", "class A {\n  init() {}\n}\n");
+        assert_formats(
+            "try{a;}\ncatch(e){b;}finally{c;}",
+            "try {\n  a;\n} catch (e) {\n  b;\n} finally {\n  c;\n}\n",
+        );
+        assert_formats(
+            "match(x){1|2=>a;3 .. -1=>{b;}_ if x>1=>c;}",
+            "match (x) {\n  1 | 2 => a;\n  3..-1 => {\n    b;\n  }\n  _ if x > 1 => c;\n}\n",
+        );
+        assert_formats(
+            "if (a) { b }\nelse { c }",
+            "if (a) {\n  b\n} else {\n  c\n}\n",
+        );
+    }
+
+    #[test]
+    fn maps() {
+        assert_formats(
+            "var m={ \"a\" :1,2:{ } } ;print m [\"a\"];",
+            "var m = {\"a\": 1, 2: {}};\nprint m[\"a\"];\n",
+        );
+        assert_formats(
+            "if (a) { print {1: [2]}; }",
+            "if (a) {\n  print {1: [2]};\n}\n",
+        );
+        assert_formats(
+            "var m = {\n\"a\": 1,\n\"b\": 2\n};",
+            "var m = {\n  \"a\": 1,\n  \"b\": 2\n};\n",
+        );
+        assert_formats("x = {\"k\": 1} [\"k\"];", "x = {\"k\": 1}[\"k\"];\n");
+    }
+
+    #[test]
+    fn comments() {
+        assert_formats(
+            "// header\n\nvar a = 1;   // one\n{ // block\n// inside\nprint a; }",
+            "\
+// header
+
+var a = 1; // one
+{ // block
+  // inside
+  print a;
+}
+",
+        );
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_formats("1 +/* two */2", "1 + /* two */ 2\n");
+        assert_formats(
+            "/* header\n   spans lines */\nvar a;",
+            "/* header\n   spans lines */\nvar a;\n",
+        );
+    }
+
+    #[test]
+    fn inline_block_comments() {
+        assert_formats("a;\n/* c */ b;", "a;\n/* c */ b;\n");
+        assert_formats("a; /* c */\nb;", "a; /* c */\nb;\n");
+        assert_formats("{\n/* c */  a;\n}", "{\n  /* c */ a;\n}\n");
+        assert_formats("a;\n/* c */\nb;", "a;\n/* c */\nb;\n");
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_formats(
+            "print \"a ${ b+1 } c ${\"${-d}\"}\" ;",
+            "print \"a ${b + 1} c ${\"${-d}\"}\";\n",
+        );
+        assert_formats(
+            "\"a ${ {\"k\": 1}[\"k\"] } b\"",
+            "\"a ${{\"k\": 1}[\"k\"]} b\"\n",
+        );
+    }
+
+    #[test]
+    fn scan_errors() {
+        assert!(format("1 + @").is_err());
+        assert!(format("\"unterminated").is_err());
+    }
+
+    fn assert_formats(source: &str, expected: &str) {
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+}
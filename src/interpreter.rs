@@ -1,20 +1,145 @@
-use crate::compiler::Compiler;
-use crate::vm::VM;
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::chunk::Chunk;
+use crate::compiler::{location, Compiler};
+use crate::debugger::DebugHook;
+use crate::objects::{ListObject, StringObject};
+use crate::stack::{Stack, DEFAULT_STACK_SIZE};
+use crate::value::Value;
+use crate::vm::{RuntimeError, Stats, Tracer, VM};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub optimize: bool,
+    pub stack_size: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            optimize: true,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+}
+
 pub struct Interpreter {
     vm: VM,
+    options: Options,
+}
+
+#[derive(Debug)]
+pub enum InterpretError {
+    Compile,
+    Runtime(RuntimeError),
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::Compile => f.write_str("Compilation failed."),
+            InterpretError::Runtime(RuntimeError::Located { error, file, line }) => {
+                write!(f, "{} Error: {error}", location(file.as_deref(), *line))
+            }
+            InterpretError::Runtime(error) => error.fmt(f),
+        }
+    }
+}
+
+impl InterpretError {
+    /// The error without where it happened, for showing next to the code
+    /// that caused it.
+    pub fn message(&self) -> String {
+        match self {
+            InterpretError::Runtime(error) => error.to_string(),
+            error => error.to_string(),
+        }
+    }
 }
 
 impl Interpreter {
-    pub fn new() -> Self {
-        Self { vm: VM::new() }
+    pub fn with_options(options: Options) -> Self {
+        let mut vm = VM::new();
+        vm.stack = Stack::with_limit(options.stack_size);
+        vm.optimize = options.optimize;
+        Self { vm, options }
     }
 
-    pub fn interpret(&mut self, source: &str) {
+    /// Makes imports relative to the script at `path` rather than to the
+    /// working directory.
+    pub fn set_script_path(&mut self, path: &Path) {
+        self.vm.path = fs::canonicalize(path).ok();
+    }
+
+    /// Defines the global `args` as a list of the script's arguments.
+    pub fn set_args(&mut self, args: &[String]) {
+        let args = args
+            .iter()
+            .map(|arg| Value::String(Rc::new(StringObject::from_owned(arg.clone()))))
+            .collect();
+        let args = Value::List(Rc::new(RefCell::new(ListObject::new(args))));
+        self.define_global("args", args);
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.vm.globals.insert(name.to_string(), value);
+    }
+
+    /// Writes an execution trace of everything run from now on to `sink`.
+    pub fn trace_to(&mut self, sink: Box<dyn Write>) {
+        self.vm.trace = Some(Tracer(sink));
+    }
+
+    pub fn attach_debugger(&mut self, debugger: Box<dyn DebugHook>) {
+        self.vm.debugger = Some(debugger);
+    }
+
+    /// Throws away all state and starts over with the same options, keeping
+    /// any trace sink and script path.
+    pub fn reset(&mut self) {
+        let trace = self.vm.trace.take();
+        let path = self.vm.path.take();
+        *self = Self::with_options(self.options);
+        self.vm.trace = trace;
+        self.vm.path = path;
+    }
+
+    pub fn interpret(&mut self, source: &str) -> Result<Value, InterpretError> {
+        let function = self.compile(source)?;
+        self.vm.run_main(&function).map_err(InterpretError::Runtime)
+    }
+
+    pub fn compile(&self, source: &str) -> Result<Chunk, InterpretError> {
         let mut compiler = Compiler::from_source(source);
+        compiler.optimize = self.options.optimize;
+        compiler.file = self.vm.path.clone();
         let function = compiler.compile();
 
-        if !compiler.had_error {
-            self.vm.run_main(&function).unwrap();
+        if compiler.had_error {
+            Err(InterpretError::Compile)
+        } else {
+            Ok(function)
         }
     }
+
+    /// The global variables defined so far, sorted by name.
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let mut globals: Vec<_> = self
+            .vm
+            .globals
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        globals.sort_by_key(|(name, _)| *name);
+        globals
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.vm.stats
+    }
 }
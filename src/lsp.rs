@@ -0,0 +1,1064 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{compiler::Compiler, protocol::Connection, scanner::Scanner, token::TokenType};
+
+/// Semantic token types, in the order the legend advertises them.
+const TOKEN_TYPES: [&str; 9] = [
+    "keyword",
+    "string",
+    "number",
+    "operator",
+    "variable",
+    "function",
+    "class",
+    "parameter",
+    "method",
+];
+/// The only semantic token modifier, set on the name in a declaration.
+const DECLARATION: u32 = 1;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+/// Serves the Language Server Protocol on stdin and stdout until the client
+/// sends `exit`.
+pub fn run() {
+    let stdin = BufReader::new(io::stdin());
+    if let Err(error) = serve(Box::new(stdin), Box::new(io::stdout())) {
+        eprintln!("Language server failed: {error}");
+    }
+}
+
+pub fn serve(reader: Box<dyn BufRead>, writer: Box<dyn Write>) -> io::Result<()> {
+    let mut server = Server {
+        connection: Connection::new(reader, writer),
+        documents: HashMap::new(),
+        shut_down: false,
+    };
+
+    while let Some(message) = server.connection.read()? {
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            break;
+        }
+        match message.get("id") {
+            Some(id) => {
+                let response = match server.request(method, &message["params"]) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                };
+                server.connection.write(&response)?;
+            }
+            None => server.notification(method, &message["params"])?,
+        }
+    }
+    Ok(())
+}
+
+struct Server {
+    connection: Connection,
+    /// Text of every open document by URI, kept whole since the client is
+    /// asked to send full documents on every change.
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        if self.shut_down {
+            return Err((INVALID_REQUEST, String::from("The server was shut down.")));
+        }
+
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = || {
+            self.documents
+                .get(uri)
+                .map(|text| Document::new(text))
+                .ok_or((INVALID_REQUEST, format!("'{uri}' is not open.")))
+        };
+        let offset = |document: &Document| {
+            let position = &params["position"];
+            document.index.offset(
+                position["line"].as_u64().unwrap_or(0) as usize,
+                position["character"].as_u64().unwrap_or(0) as usize,
+            )
+        };
+
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": ["declaration"] },
+                        "full": true,
+                    },
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "rux" },
+            })),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/semanticTokens/full" => {
+                Ok(json!({ "data": document()?.semantic_tokens() }))
+            }
+            "textDocument/definition" => {
+                let document = document()?;
+                Ok(match document.outline.declaration_at(offset(&document)) {
+                    Some(declaration) => json!({
+                        "uri": uri,
+                        "range": document.range(declaration.start, declaration.stop),
+                    }),
+                    None => Json::Null,
+                })
+            }
+            "textDocument/hover" => {
+                let document = document()?;
+                Ok(match document.outline.declaration_at(offset(&document)) {
+                    Some(declaration) => {
+                        let (line, _) = document.index.position(declaration.start);
+                        json!({ "contents": {
+                            "kind": "markdown",
+                            "value": format!(
+                                "```lox\n{}\n```\nDeclared on line {}.",
+                                declaration.detail,
+                                line + 1
+                            ),
+                        } })
+                    }
+                    None => Json::Null,
+                })
+            }
+            "textDocument/documentSymbol" => Ok(json!(document()?.symbols(None))),
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported request '{method}'."))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return self.publish(uri, vec![]);
+            }
+            _ => return Ok(()),
+        };
+
+        if let Some(text) = text {
+            self.documents.insert(uri.to_string(), text.to_string());
+            let diagnostics = Document::new(text).diagnostics();
+            self.publish(uri, diagnostics)?;
+        }
+        Ok(())
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+        self.connection.write(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+}
+
+/// An open document along with everything worked out from its tokens.
+struct Document<'a> {
+    text: &'a str,
+    index: LineIndex<'a>,
+    outline: Outline,
+}
+
+impl<'a> Document<'a> {
+    fn new(text: &'a str) -> Self {
+        Document {
+            text,
+            index: LineIndex::new(text),
+            outline: Outline::new(text),
+        }
+    }
+
+    fn range(&self, start: usize, stop: usize) -> Json {
+        let (start_line, start_character) = self.index.position(start);
+        let (stop_line, stop_character) = self.index.position(stop);
+        json!({
+            "start": { "line": start_line, "character": start_character },
+            "end": { "line": stop_line, "character": stop_character },
+        })
+    }
+
+    /// Compiler errors and warnings only know their line, so they cover all
+    /// of it.
+    fn diagnostics(&self) -> Vec<Json> {
+        let mut compiler = Compiler::from_source(self.text);
+        compiler.print_errors = false;
+        compiler.optimize = false;
+        compiler.compile();
+
+        compiler
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let line = diagnostic.line as usize - 1;
+                json!({
+                    "range": {
+                        "start": { "line": line, "character": 0 },
+                        "end": { "line": line, "character": self.index.line_length(line) },
+                    },
+                    "severity": if diagnostic.warning { 2 } else { 1 },
+                    "source": "rux",
+                    "message": diagnostic.message,
+                })
+            })
+            .collect()
+    }
+
+    /// Encodes every token as the protocol's relative five-number groups.
+    /// Tokens spanning lines, such as multi-line strings, are left out.
+    fn semantic_tokens(&self) -> Vec<u32> {
+        let mut data = vec![];
+        let (mut previous_line, mut previous_character) = (0, 0);
+
+        for lexeme in &self.outline.lexemes {
+            let text = self.text.get(lexeme.start..lexeme.stop).unwrap_or_default();
+            let Some(token_type) = self.outline.semantic_type(lexeme) else {
+                continue;
+            };
+            if text.contains('\n') {
+                continue;
+            }
+            let modifiers = match self
+                .outline
+                .declarations
+                .iter()
+                .any(|d| d.start == lexeme.start)
+            {
+                true => DECLARATION,
+                false => 0,
+            };
+
+            let (line, character) = self.index.position(lexeme.start);
+            let delta_character = match line == previous_line {
+                true => character - previous_character,
+                false => character,
+            };
+            data.extend([
+                line - previous_line,
+                delta_character,
+                text.encode_utf16().count() as u32,
+                token_type,
+                modifiers,
+            ]);
+            (previous_line, previous_character) = (line, character);
+        }
+        data
+    }
+
+    /// Functions and classes declared directly inside `parent`, with their
+    /// own functions and methods as children.
+    fn symbols(&self, parent: Option<usize>) -> Vec<Json> {
+        self.outline
+            .declarations
+            .iter()
+            .enumerate()
+            .filter(|(_, declaration)| declaration.parent == parent)
+            .filter_map(|(index, declaration)| {
+                let kind = match declaration.kind {
+                    Kind::Class => 5,
+                    Kind::Method => 6,
+                    Kind::Function => 12,
+                    Kind::Variable | Kind::Parameter => return None,
+                };
+                Some(json!({
+                    "name": declaration.name,
+                    "detail": declaration.detail,
+                    "kind": kind,
+                    "range": self.range(declaration.keyword, declaration.end),
+                    "selectionRange": self.range(declaration.start, declaration.stop),
+                    "children": self.symbols(Some(index)),
+                }))
+            })
+            .collect()
+    }
+}
+
+/// Converts between byte offsets and the protocol's line and UTF-16
+/// character positions.
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        LineIndex { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> (u32, u32) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = self
+            .text
+            .get(self.starts[line]..offset)
+            .map_or(0, |text| text.encode_utf16().count());
+        (line as u32, character as u32)
+    }
+
+    fn offset(&self, line: usize, character: usize) -> usize {
+        let Some(&start) = self.starts.get(line) else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (offset, c) in self.text[start..].char_indices() {
+            if units >= character || c == '\n' {
+                return start + offset;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    fn line_length(&self, line: usize) -> usize {
+        let start = self.starts.get(line).copied().unwrap_or(self.text.len());
+        let end = self
+            .starts
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.text[start..end]
+            .trim_end_matches(['\n', '\r'])
+            .encode_utf16()
+            .count()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Variable,
+    Function,
+    Class,
+    Parameter,
+    Method,
+}
+
+#[derive(Debug)]
+struct Declaration {
+    name: String,
+    kind: Kind,
+    /// Offsets of the declared name.
+    start: usize,
+    stop: usize,
+    /// Offset of the keyword starting the declaration and of its end, the
+    /// closing brace for functions and classes.
+    keyword: usize,
+    end: usize,
+    detail: String,
+    /// The function or class this is declared in.
+    parent: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lexeme {
+    token_type: TokenType,
+    start: usize,
+    stop: usize,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    declarations: Vec<usize>,
+    owner: Option<usize>,
+    /// Index of the last lexeme of the loop or catch clause binding the
+    /// declarations, for scopes that don't follow braces.
+    closes: Option<usize>,
+}
+
+/// Declarations and the names referring to them, found from the tokens
+/// alone so a document can be navigated even while it doesn't compile.
+///
+/// Scopes follow braces: names declared in a block are visible until it
+/// closes, parameters belong to the function body, loop and error
+/// variables to the statement after them, and globals can be referred to
+/// before they are declared.
+#[derive(Debug, Default)]
+struct Outline {
+    lexemes: Vec<Lexeme>,
+    declarations: Vec<Declaration>,
+    /// Start offset of each resolved name and the declaration it refers to.
+    references: HashMap<usize, usize>,
+}
+
+impl Outline {
+    fn new(text: &str) -> Self {
+        let mut outline = Outline::default();
+        for token in Scanner::new(text).tokenize() {
+            if token.token_type == TokenType::Eof {
+                break;
+            }
+            if let Ok(data) = &token.data {
+                outline.lexemes.push(Lexeme {
+                    token_type: token.token_type,
+                    start: data.start,
+                    stop: data.stop,
+                });
+            }
+        }
+        outline.resolve(text);
+        outline
+    }
+
+    fn resolve(&mut self, text: &str) {
+        let name = |lexeme: &Lexeme| text[lexeme.start..lexeme.stop].to_string();
+        let mut scopes = vec![Scope::default()];
+        let mut pending_body: Option<Scope> = None;
+        let mut unresolved = vec![];
+
+        let mut i = 0;
+        while i < self.lexemes.len() {
+            while scopes
+                .last()
+                .and_then(|scope| scope.closes)
+                .is_some_and(|last| last < i)
+            {
+                scopes.pop();
+            }
+            let token_type = |offset: usize| self.lexemes.get(i + offset).map(|l| l.token_type);
+            let owner = scopes.last().and_then(|scope| scope.owner);
+            let in_class_body =
+                owner.is_some_and(|owner| self.declarations[owner].kind == Kind::Class);
+            let after_dot = i > 0 && self.lexemes[i - 1].token_type == TokenType::Dot;
+
+            match (token_type(0), token_type(1)) {
+                (Some(TokenType::Var), Some(TokenType::Identifier)) => {
+                    let detail = format!("var {}", name(&self.lexemes[i + 1]));
+                    let declaration = self.declare(i + 1, Kind::Variable, i, detail, owner, text);
+                    scopes.last_mut().unwrap().declarations.push(declaration);
+                    i += 2;
+                }
+                (Some(TokenType::Fun), Some(TokenType::Identifier)) => {
+                    let function = i + 1;
+                    let (body, next) = self.function(function, Kind::Function, i, owner, text);
+                    let declaration = body.owner.unwrap();
+                    scopes.last_mut().unwrap().declarations.push(declaration);
+                    pending_body = Some(body);
+                    i = next;
+                }
+                (Some(TokenType::Identifier), Some(TokenType::LeftParen))
+                    if in_class_body && !after_dot =>
+                {
+                    let (body, next) = self.function(i, Kind::Method, i, owner, text);
+                    pending_body = Some(body);
+                    i = next;
+                }
+                (Some(TokenType::Class), Some(TokenType::Identifier)) => {
+                    let mut detail = format!("class {}", name(&self.lexemes[i + 1]));
+                    if let (Some(TokenType::Less), Some(superclass)) =
+                        (token_type(2), self.lexemes.get(i + 3))
+                    {
+                        detail = format!("{detail} < {}", name(superclass));
+                    }
+                    let declaration = self.declare(i + 1, Kind::Class, i, detail, owner, text);
+                    scopes.last_mut().unwrap().declarations.push(declaration);
+                    pending_body = Some(Scope {
+                        declarations: vec![],
+                        owner: Some(declaration),
+                        closes: None,
+                    });
+                    i += 2;
+                }
+                (Some(TokenType::For), Some(TokenType::LeftParen))
+                    if token_type(2) == Some(TokenType::Identifier) =>
+                {
+                    let detail = format!("for {}", name(&self.lexemes[i + 2]));
+                    let declaration = self.declare(i + 2, Kind::Variable, i, detail, owner, text);
+                    self.bind_statement(&mut scopes, declaration, i + 1);
+                    i += 3;
+                }
+                (Some(TokenType::Catch), Some(TokenType::LeftParen))
+                    if token_type(2) == Some(TokenType::Identifier) =>
+                {
+                    let detail = format!("catch ({})", name(&self.lexemes[i + 2]));
+                    let declaration = self.declare(i + 2, Kind::Variable, i, detail, owner, text);
+                    self.bind_statement(&mut scopes, declaration, i + 1);
+                    i += 3;
+                }
+                (Some(TokenType::Import), Some(TokenType::String))
+                    if token_type(2) == Some(TokenType::As)
+                        && token_type(3) == Some(TokenType::Identifier) =>
+                {
+                    let detail = format!(
+                        "import {} as {}",
+                        name(&self.lexemes[i + 1]),
+                        name(&self.lexemes[i + 3])
+                    );
+                    let declaration = self.declare(i + 3, Kind::Variable, i, detail, owner, text);
+                    scopes.last_mut().unwrap().declarations.push(declaration);
+                    i += 4;
+                }
+                (Some(TokenType::From), Some(TokenType::String))
+                    if token_type(2) == Some(TokenType::Import) =>
+                {
+                    let path = name(&self.lexemes[i + 1]);
+                    let mut next = i + 3;
+                    let is = |index: usize, token_type| {
+                        self.lexemes.get(index).map(|l| l.token_type) == Some(token_type)
+                    };
+                    let mut names = vec![];
+                    while is(next, TokenType::Identifier) {
+                        names.push(next);
+                        next += 1;
+                        if !is(next, TokenType::Comma) {
+                            break;
+                        }
+                        next += 1;
+                    }
+                    for imported in names {
+                        let detail =
+                            format!("from {path} import {}", name(&self.lexemes[imported]));
+                        let declaration =
+                            self.declare(imported, Kind::Variable, i, detail, owner, text);
+                        scopes.last_mut().unwrap().declarations.push(declaration);
+                    }
+                    i = next;
+                }
+                (Some(TokenType::LeftBrace), _) => {
+                    scopes.push(pending_body.take().unwrap_or_default());
+                    i += 1;
+                }
+                (Some(TokenType::RightBrace), _) => {
+                    if scopes.len() > 1 {
+                        if let Some(owner) = scopes.pop().and_then(|scope| scope.owner) {
+                            self.declarations[owner].end = self.lexemes[i].stop;
+                        }
+                    }
+                    i += 1;
+                }
+                (Some(TokenType::Identifier), _) if !after_dot => {
+                    let reference = &self.lexemes[i];
+                    let name = name(reference);
+                    let declaration = scopes.iter().rev().find_map(|scope| {
+                        scope
+                            .declarations
+                            .iter()
+                            .rev()
+                            .find(|&&declaration| self.declarations[declaration].name == name)
+                    });
+                    match declaration {
+                        Some(&declaration) => {
+                            self.references.insert(reference.start, declaration);
+                        }
+                        None => unresolved.push((reference.start, name)),
+                    }
+                    i += 1;
+                }
+                _ => i += 1,
+            }
+        }
+
+        for (start, name) in unresolved {
+            let global = scopes[0]
+                .declarations
+                .iter()
+                .find(|&&declaration| self.declarations[declaration].name == name);
+            if let Some(&declaration) = global {
+                self.references.insert(start, declaration);
+            }
+        }
+    }
+
+    /// Opens a scope holding `declaration` for the statement after the
+    /// parenthesized header starting at `paren`, such as a loop body.
+    fn bind_statement(&self, scopes: &mut Vec<Scope>, declaration: usize, paren: usize) {
+        let body = self.closing(paren) + 1;
+        scopes.push(Scope {
+            declarations: vec![declaration],
+            owner: None,
+            closes: Some(self.statement_end(body)),
+        });
+    }
+
+    /// Index of the lexeme closing the bracket at `open`, or of the last
+    /// lexeme if it isn't closed.
+    fn closing(&self, open: usize) -> usize {
+        let mut depth = 0;
+        for (index, lexeme) in self.lexemes.iter().enumerate().skip(open) {
+            match lexeme.token_type {
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return index;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.lexemes.len().saturating_sub(1)
+    }
+
+    /// Index of the last lexeme of the statement starting at `start`: its
+    /// closing brace or semicolon. A statement made of several blocks, like
+    /// `try { } catch (e) { }`, ends with the first of them.
+    fn statement_end(&self, start: usize) -> usize {
+        let mut index = start;
+        while let Some(lexeme) = self.lexemes.get(index) {
+            match lexeme.token_type {
+                TokenType::Semicolon => return index,
+                TokenType::LeftBrace => return self.closing(index),
+                TokenType::LeftParen | TokenType::LeftBracket => index = self.closing(index),
+                // The enclosing block ends first.
+                TokenType::RightBrace => return index.saturating_sub(1),
+                _ => (),
+            }
+            index += 1;
+        }
+        self.lexemes.len().saturating_sub(1)
+    }
+
+    /// Declares the function or method named at `name` along with its
+    /// parameters, returning the scope of its body and where to carry on.
+    fn function(
+        &mut self,
+        name: usize,
+        kind: Kind,
+        keyword: usize,
+        parent: Option<usize>,
+        text: &str,
+    ) -> (Scope, usize) {
+        let mut parameters = vec![];
+        let mut next = name + 1;
+        if self.lexemes.get(next).map(|l| l.token_type) == Some(TokenType::LeftParen) {
+            next += 1;
+            while let Some(lexeme) = self.lexemes.get(next) {
+                next += 1;
+                match lexeme.token_type {
+                    TokenType::Identifier => parameters.push(next - 1),
+                    TokenType::RightParen => break,
+                    _ => (),
+                }
+            }
+        }
+
+        let text_of = |index: usize| {
+            let lexeme = &self.lexemes[index];
+            &text[lexeme.start..lexeme.stop]
+        };
+        let signature = format!(
+            "{}({})",
+            text_of(name),
+            parameters
+                .iter()
+                .map(|&parameter| text_of(parameter))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let detail = match (kind, parent) {
+            (Kind::Method, Some(class)) => {
+                format!("fun {}.{signature}", self.declarations[class].name)
+            }
+            _ => format!("fun {signature}"),
+        };
+        let parameter_details: Vec<String> = parameters
+            .iter()
+            .map(|&parameter| format!("(parameter) {}", text_of(parameter)))
+            .collect();
+
+        let function = self.declare(name, kind, keyword, detail, parent, text);
+        let declarations = parameters
+            .iter()
+            .zip(parameter_details)
+            .map(|(&parameter, detail)| {
+                self.declare(
+                    parameter,
+                    Kind::Parameter,
+                    parameter,
+                    detail,
+                    Some(function),
+                    text,
+                )
+            })
+            .collect();
+
+        let body = Scope {
+            declarations,
+            owner: Some(function),
+            closes: None,
+        };
+        (body, next)
+    }
+
+    fn declare(
+        &mut self,
+        name: usize,
+        kind: Kind,
+        keyword: usize,
+        detail: String,
+        parent: Option<usize>,
+        text: &str,
+    ) -> usize {
+        let Lexeme { start, stop, .. } = self.lexemes[name];
+        self.declarations.push(Declaration {
+            name: text[start..stop].to_string(),
+            kind,
+            start,
+            stop,
+            keyword: self.lexemes[keyword].start,
+            end: stop,
+            detail,
+            parent,
+        });
+        self.declarations.len() - 1
+    }
+
+    /// The declaration named at `offset`, either where it is declared or
+    /// where it is used.
+    fn declaration_at(&self, offset: usize) -> Option<&Declaration> {
+        let name = self.lexemes.iter().find(|lexeme| {
+            lexeme.token_type == TokenType::Identifier
+                && lexeme.start <= offset
+                && offset <= lexeme.stop
+        })?;
+        self.declaration_of(name)
+    }
+
+    fn declaration_of(&self, name: &Lexeme) -> Option<&Declaration> {
+        match self.references.get(&name.start) {
+            Some(&declaration) => Some(&self.declarations[declaration]),
+            None => self
+                .declarations
+                .iter()
+                .find(|declaration| declaration.start == name.start),
+        }
+    }
+
+    /// Index into `TOKEN_TYPES`, or `None` for punctuation.
+    fn semantic_type(&self, lexeme: &Lexeme) -> Option<u32> {
+        let token_type = match lexeme.token_type {
+            TokenType::String | TokenType::Interpolation => "string",
+            TokenType::Number => "number",
+            TokenType::Identifier => match self.declaration_of(lexeme).map(|d| d.kind) {
+                Some(Kind::Function) => "function",
+                Some(Kind::Class) => "class",
+                Some(Kind::Parameter) => "parameter",
+                Some(Kind::Method) => "method",
+                Some(Kind::Variable) | None => "variable",
+            },
+            TokenType::And
+            | TokenType::As
+            | TokenType::Break
+            | TokenType::Catch
+            | TokenType::Class
+            | TokenType::Continue
+            | TokenType::Else
+            | TokenType::False
+            | TokenType::Finally
+            | TokenType::For
+            | TokenType::From
+            | TokenType::Fun
+            | TokenType::If
+            | TokenType::Import
+            | TokenType::In
+            | TokenType::Match
+            | TokenType::Nil
+            | TokenType::Or
+            | TokenType::Print
+            | TokenType::Return
+            | TokenType::Super
+            | TokenType::This
+            | TokenType::Throw
+            | TokenType::True
+            | TokenType::Try
+            | TokenType::Var
+            | TokenType::While => "keyword",
+            TokenType::Minus
+            | TokenType::Plus
+            | TokenType::Slash
+            | TokenType::Star
+            | TokenType::Percent
+            | TokenType::StarStar
+            | TokenType::PlusEqual
+            | TokenType::MinusEqual
+            | TokenType::StarEqual
+            | TokenType::SlashEqual
+            | TokenType::PercentEqual
+            | TokenType::DotDot
+            | TokenType::DotDotEqual
+            | TokenType::EqualGreater
+            | TokenType::Ampersand
+            | TokenType::Pipe
+            | TokenType::Caret
+            | TokenType::LessLess
+            | TokenType::GreaterGreater
+            | TokenType::Bang
+            | TokenType::BangEqual
+            | TokenType::Equal
+            | TokenType::EqualEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => "operator",
+            _ => return None,
+        };
+        TOKEN_TYPES
+            .iter()
+            .position(|&name| name == token_type)
+            .map(|index| index as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::{json, Value as Json};
+
+    use super::serve;
+    use crate::protocol::{frame, unframe, SharedBuffer};
+
+    const URI: &str = "file:///test.lox";
+    const SOURCE: &str = "\
+var total = 1;
+fun add(a, b) {
+  return a + b + total;
+}
+class Pair < Base {
+  sum() { return add(1, 2); }
+}
+";
+
+    #[test]
+    fn diagnostics() {
+        let messages = session(&[
+            notification(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": { "uri": URI, "text": "1 +\n(2" }
+                }),
+            ),
+            notification(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": URI },
+                    "contentChanges": [{ "text": "1 + 2" }],
+                }),
+            ),
+            notification(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": URI },
+                    "contentChanges": [{ "text": "match (1) { _ => 1; 2 => 2; }" }],
+                }),
+            ),
+        ]);
+
+        let published: Vec<&Json> = messages
+            .iter()
+            .map(|message| &message["params"]["diagnostics"])
+            .collect();
+        assert_eq!(published.len(), 3);
+        assert_eq!(published[0].as_array().unwrap().len(), 1);
+        assert_eq!(published[0][0]["severity"], 1);
+        assert_eq!(published[0][0]["range"]["start"]["line"], 1);
+        assert_eq!(published[0][0]["range"]["end"]["character"], 2);
+        assert_eq!(published[1], &json!([]));
+        assert_eq!(published[2][0]["severity"], 2);
+    }
+
+    #[test]
+    fn navigation() {
+        let at = |line: u32, character: u32| {
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+            })
+        };
+        let messages = session(&[
+            notification(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": { "uri": URI, "text": SOURCE }
+                }),
+            ),
+            request(1, "textDocument/definition", at(2, 18)),
+            request(2, "textDocument/definition", at(2, 13)),
+            request(3, "textDocument/hover", at(5, 18)),
+            request(4, "textDocument/hover", at(2, 3)),
+            request(5, "textDocument/documentSymbol", at(0, 0)),
+        ]);
+        let result = |id: u64| {
+            messages.iter().find(|message| message["id"] == id).unwrap()["result"].clone()
+        };
+
+        assert_eq!(
+            result(1)["range"]["start"],
+            json!({ "line": 0, "character": 4 })
+        );
+        assert_eq!(
+            result(2)["range"]["start"],
+            json!({ "line": 1, "character": 11 })
+        );
+        assert_eq!(
+            result(3)["contents"]["value"],
+            "```lox\nfun add(a, b)\n```\nDeclared on line 2."
+        );
+        assert_eq!(result(4), Json::Null);
+
+        let symbols = result(5);
+        let names: Vec<&Json> = symbols
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| &s["name"])
+            .collect();
+        assert_eq!(names, vec!["add", "Pair"]);
+        assert_eq!(
+            symbols[0]["range"]["end"],
+            json!({ "line": 3, "character": 1 })
+        );
+        assert_eq!(symbols[1]["detail"], "class Pair < Base");
+        assert_eq!(symbols[1]["children"][0]["detail"], "fun Pair.sum()");
+    }
+
+    #[test]
+    fn bindings() {
+        let source = "\
+import \"util.lox\" as util;
+from \"util.lox\" import a, b;
+var total = 0;
+for (x in [a, b]) total = total + x;
+for (x in [1]) { util.use(x); }
+try { total; } catch (e) { e; }
+x;
+";
+        let at = |line: u32, character: u32| {
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+            })
+        };
+        let messages = session(&[
+            notification(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": { "uri": URI, "text": source }
+                }),
+            ),
+            request(1, "textDocument/definition", at(4, 17)),
+            request(2, "textDocument/definition", at(3, 14)),
+            request(3, "textDocument/definition", at(3, 34)),
+            request(4, "textDocument/definition", at(4, 26)),
+            request(5, "textDocument/definition", at(5, 27)),
+            request(6, "textDocument/definition", at(6, 0)),
+            request(7, "textDocument/hover", at(4, 17)),
+        ]);
+        let start = |id: u64| {
+            messages.iter().find(|message| message["id"] == id).unwrap()["result"]["range"]["start"]
+                .clone()
+        };
+
+        assert_eq!(start(1), json!({ "line": 0, "character": 21 }));
+        assert_eq!(start(2), json!({ "line": 1, "character": 26 }));
+        assert_eq!(start(3), json!({ "line": 3, "character": 5 }));
+        assert_eq!(start(4), json!({ "line": 4, "character": 5 }));
+        assert_eq!(start(5), json!({ "line": 5, "character": 22 }));
+        assert_eq!(start(6), Json::Null);
+        assert_eq!(
+            messages[7]["result"]["contents"]["value"],
+            "```lox\nimport \"util.lox\" as util\n```\nDeclared on line 1."
+        );
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let messages = session(&[
+            notification(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": { "uri": URI, "text": "fun f(a) {\n  return a + \"s\";\n}" }
+                }),
+            ),
+            request(
+                1,
+                "textDocument/semanticTokens/full",
+                json!({
+                    "textDocument": { "uri": URI }
+                }),
+            ),
+        ]);
+
+        let data = &messages[1]["result"]["data"];
+        assert_eq!(
+            data,
+            &json!([
+                0, 0, 3, 0, 0, // fun
+                0, 4, 1, 5, 1, // f
+                0, 2, 1, 7, 1, // a
+                1, 2, 6, 0, 0, // return
+                0, 7, 1, 7, 0, // a
+                0, 2, 1, 3, 0, // +
+                0, 2, 3, 1, 0, // "s"
+            ])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let messages = session(&[
+            request(
+                1,
+                "textDocument/hover",
+                json!({
+                    "textDocument": { "uri": URI },
+                    "position": { "line": 0, "character": 0 },
+                }),
+            ),
+            request(2, "workspace/symbol", json!({})),
+            request(3, "shutdown", Json::Null),
+            request(4, "initialize", json!({})),
+        ]);
+
+        assert!(messages[0]["error"]["message"].is_string());
+        assert_eq!(messages[1]["error"]["code"], -32601);
+        assert_eq!(messages[2]["result"], Json::Null);
+        assert_eq!(messages[3]["error"]["code"], -32600);
+    }
+
+    fn request(id: u64, method: &str, params: Json) -> Json {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    /// Plays the messages to a language server and returns everything it
+    /// sent back.
+    fn session(messages: &[Json]) -> Vec<Json> {
+        let output = SharedBuffer::default();
+        serve(
+            Box::new(Cursor::new(frame(messages))),
+            Box::new(output.clone()),
+        )
+        .unwrap();
+        let output = output.0.borrow().clone();
+        unframe(output)
+    }
+}
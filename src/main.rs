@@ -1,66 +1,421 @@
-use crate::interpreter::Interpreter;
+use crate::{
+    debugger::TerminalDebugger,
+    interpreter::{InterpretError, Interpreter, Options},
+    scanner::Scanner,
+};
 use std::{
-    env, fs,
-    io::{self, Write},
+    env,
+    fs::{self, File},
+    io,
+    path::Path,
+    process,
+    time::Instant,
 };
+mod builtins;
 mod chunk;
 mod compiler;
+mod dap;
+mod debugger;
+mod formatter;
 mod interpreter;
+mod lsp;
 mod objects;
+mod optimizer;
 mod precedence;
+mod protocol;
+mod repl;
 mod scanner;
 mod stack;
 mod token;
 mod value;
 mod vm;
 
+const USAGE: &str = "\
+Usage: rux [command] [options] [file] [args...]
+
+Commands:
+  run <file> [args]   run a script (the default when only a file is given),
+                      with any arguments after it in the global list 'args'
+  repl                start the interactive prompt (the default)
+  dis <file>          print the compiled bytecode of a script
+  tokens <file>       print the tokens the scanner produces for a script
+  check <file>        report compile errors without running
+  fmt <file>          re-print a script in the canonical style
+  debug <file> [args] run a script under the interactive debugger
+  dap                 serve the Debug Adapter Protocol on stdin and stdout
+  lsp                 serve the Language Server Protocol on stdin and stdout
+
+Options:
+  --trace             print the stack and every instruction to stderr as it runs
+  --trace-file <path> write that trace to a file instead
+  --stack-size <n>    limit the VM stack to n values
+  --no-optimize       skip the peephole optimizer
+  --bench             report time, instructions and allocations on stderr
+  --check             with fmt, fail instead of rewriting an unformatted file
+  --trivia            with tokens, include whitespace and comments
+  --                  pass everything after it to the script as arguments
+  -h, --help          show this message";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Repl,
+    Run(String),
+    Dis(String),
+    Tokens(String),
+    Check(String),
+    Fmt(String),
+    Debug(String),
+    Dap,
+    Lsp,
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+enum TraceTarget {
+    Stderr,
+    File(String),
+}
+
+#[derive(Debug)]
+struct Cli {
+    command: Command,
+    options: Options,
+    trace: Option<TraceTarget>,
+    bench: bool,
+    check: bool,
+    trivia: bool,
+    /// Arguments for the script, after its path.
+    args: Vec<String>,
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        1 => repl(),
-        2 => run_file(args.get(1).unwrap()),
-        _ => panic!("Too many args!!!!"),
-    }
-}
-
-fn run_file(path: &str) {
-    println!("Running file at {path}");
-    let contents = fs::read_to_string(path).expect("Something went wrong when reading the file");
-    run_source(&contents);
-}
-
-fn run_source(source: &str) {
-    let mut interpreter = Interpreter::new();
-    interpreter.interpret(source);
-}
-
-fn repl() {
-    let stdin = io::stdin();
-    let mut interpreter = Interpreter::new();
-
-    loop {
-        print!("> ");
-        io::stdout().flush().expect("Failed to flush to stdout");
-
-        let mut source = String::new();
-
-        loop {
-            let mut input = String::new();
-            match stdin.read_line(&mut input) {
-                Ok(_) => {
-                    input = input.to_string();
-                    if input.trim().is_empty() {
-                        break;
-                    } else {
-                        source.push_str(&input.trim_end());
-                    }
-                }
-                Err(error) => {
-                    println!("error: {error}");
-                    break;
-                }
+    let cli = match parse_args(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("rux: {message}\n\n{USAGE}");
+            process::exit(64);
+        }
+    };
+
+    let interpreter = interpreter(&cli);
+    match cli.command {
+        Command::Repl => repl::run(interpreter),
+        Command::Run(path) if cli.bench => bench_file(&path, &cli.args, interpreter),
+        Command::Run(path) => run_file(&path, &cli.args, interpreter),
+        Command::Dis(path) => disassemble_file(&path, interpreter),
+        Command::Tokens(path) => print_tokens(&path, cli.trivia),
+        Command::Check(path) => check_file(&path, interpreter),
+        Command::Fmt(path) => format_file(&path, cli.check),
+        Command::Debug(path) => debug_file(&path, &cli.args, interpreter),
+        Command::Dap => dap::run(interpreter),
+        Command::Lsp => lsp::run(),
+        Command::Help => println!("{USAGE}"),
+    }
+}
+
+fn interpreter(cli: &Cli) -> Interpreter {
+    let mut interpreter = Interpreter::with_options(cli.options);
+    match &cli.trace {
+        Some(TraceTarget::Stderr) => interpreter.trace_to(Box::new(io::stderr())),
+        Some(TraceTarget::File(path)) => match File::create(path) {
+            Ok(file) => interpreter.trace_to(Box::new(file)),
+            Err(error) => {
+                eprintln!("Could not create trace file '{path}': {error}");
+                process::exit(73);
+            }
+        },
+        None => (),
+    }
+    interpreter
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut options = Options::default();
+    let mut trace = None;
+    let mut bench = false;
+    let mut check = false;
+    let mut trivia = false;
+    let mut positional = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = Some(TraceTarget::Stderr),
+            "--trace-file" => {
+                let path = args.next().ok_or("--trace-file needs a path")?;
+                trace = Some(TraceTarget::File(path));
+            }
+            "--no-optimize" => options.optimize = false,
+            "--bench" => bench = true,
+            "--check" => check = true,
+            "--trivia" => trivia = true,
+            "--stack-size" => {
+                let size = args.next().ok_or("--stack-size needs a value")?;
+                options.stack_size = size
+                    .parse()
+                    .map_err(|_| format!("Invalid stack size '{size}'"))?;
+            }
+            "-h" | "--help" => positional.insert(0, String::from("help")),
+            "--" => positional.extend(args.by_ref()),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{flag}'")),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        None | Some("repl") => Command::Repl,
+        Some("help") => Command::Help,
+        Some("dap") => Command::Dap,
+        Some("lsp") => Command::Lsp,
+        Some(command @ ("run" | "dis" | "tokens" | "check" | "fmt" | "debug")) => {
+            let path = positional
+                .next()
+                .ok_or(format!("'{command}' needs a file"))?;
+            match command {
+                "run" => Command::Run(path),
+                "dis" => Command::Dis(path),
+                "tokens" => Command::Tokens(path),
+                "check" => Command::Check(path),
+                "fmt" => Command::Fmt(path),
+                _ => Command::Debug(path),
             }
         }
-        interpreter.interpret(&source);
+        Some(path) => Command::Run(path.to_string()),
+    };
+
+    // Only scripts take arguments, and help ignores anything else.
+    let args: Vec<String> = positional.collect();
+    let takes_args = matches!(command, Command::Run(_) | Command::Debug(_) | Command::Help);
+    if let (false, Some(extra)) = (takes_args, args.first()) {
+        return Err(format!("Unexpected argument '{extra}'"));
+    }
+    if bench && !matches!(command, Command::Run(_)) {
+        return Err(String::from("--bench only works with 'run'"));
+    }
+    if check && !matches!(command, Command::Fmt(_)) {
+        return Err(String::from("--check only works with 'fmt'"));
+    }
+    if trivia && !matches!(command, Command::Tokens(_)) {
+        return Err(String::from("--trivia only works with 'tokens'"));
+    }
+
+    Ok(Cli {
+        command,
+        options,
+        trace,
+        bench,
+        check,
+        trivia,
+        args,
+    })
+}
+
+fn read_file(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("Could not read '{path}': {error}");
+        process::exit(66);
+    })
+}
+
+fn run_file(path: &str, args: &[String], mut interpreter: Interpreter) {
+    let contents = read_file(path);
+    interpreter.set_script_path(Path::new(path));
+    interpreter.set_args(args);
+    if let Err(error) = interpreter.interpret(&contents) {
+        exit_with(error);
+    }
+}
+
+/// Runs a file once and reports timing and VM counters on stderr, keeping
+/// stdout free for the program's own output.
+fn bench_file(path: &str, args: &[String], mut interpreter: Interpreter) {
+    let contents = read_file(path);
+    interpreter.set_script_path(Path::new(path));
+    interpreter.set_args(args);
+
+    let start = Instant::now();
+    let result = interpreter.interpret(&contents);
+    let elapsed = start.elapsed();
+
+    if let Err(error) = result {
+        exit_with(error);
+    }
+    eprintln!("time: {}", elapsed.as_secs_f64());
+    eprintln!("{}", interpreter.stats());
+}
+
+fn disassemble_file(path: &str, mut interpreter: Interpreter) {
+    let contents = read_file(path);
+    interpreter.set_script_path(Path::new(path));
+    match interpreter.compile(&contents) {
+        Ok(chunk) => chunk.disassemble(path),
+        Err(error) => exit_with(error),
+    }
+}
+
+fn print_tokens(path: &str, trivia: bool) {
+    let contents = read_file(path);
+    let scanner = match trivia {
+        true => Scanner::with_trivia(&contents),
+        false => Scanner::new(&contents),
+    };
+    for token in scanner.tokenize() {
+        let position = format!("{}:{}", token.line, token.column);
+        match &token.data {
+            Ok(data) => println!(
+                "{:>7} {:<14} '{}'",
+                position,
+                format!("{:?}", token.token_type),
+                data.lexeme.escape_debug()
+            ),
+            Err(message) => println!("{:>7} {:<14} {}", position, "Error", message),
+        }
+    }
+}
+
+fn check_file(path: &str, mut interpreter: Interpreter) {
+    let contents = read_file(path);
+    interpreter.set_script_path(Path::new(path));
+    if let Err(error) = interpreter.compile(&contents) {
+        exit_with(error);
+    }
+}
+
+fn format_file(path: &str, check: bool) {
+    let contents = read_file(path);
+    let formatted = formatter::format(&contents).unwrap_or_else(|error| {
+        eprintln!("{error}");
+        process::exit(65);
+    });
+
+    if formatted == contents {
+        return;
+    }
+    if check {
+        eprintln!("'{path}' is not formatted.");
+        process::exit(1);
+    }
+    if let Err(error) = fs::write(path, formatted) {
+        eprintln!("Could not write '{path}': {error}");
+        process::exit(74);
+    }
+}
+
+fn debug_file(path: &str, args: &[String], mut interpreter: Interpreter) {
+    let contents = read_file(path);
+    interpreter.set_script_path(Path::new(path));
+    interpreter.set_args(args);
+    interpreter.attach_debugger(Box::new(TerminalDebugger::new(path, &contents)));
+    if let Err(error) = interpreter.interpret(&contents) {
+        exit_with(error);
+    }
+}
+
+fn exit_with(error: InterpretError) -> ! {
+    eprintln!("{error}");
+    match error {
+        InterpretError::Compile => process::exit(65),
+        InterpretError::Runtime(_) => process::exit(70),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, Cli, Command, TraceTarget};
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse(&[]).command, Command::Repl);
+        assert_eq!(parse(&["repl"]).command, Command::Repl);
+        assert_eq!(
+            parse(&["a.lox"]).command,
+            Command::Run(String::from("a.lox"))
+        );
+        assert_eq!(
+            parse(&["run", "a.lox"]).command,
+            Command::Run(String::from("a.lox"))
+        );
+        assert_eq!(
+            parse(&["dis", "a.lox"]).command,
+            Command::Dis(String::from("a.lox"))
+        );
+        assert_eq!(
+            parse(&["check", "a.lox"]).command,
+            Command::Check(String::from("a.lox"))
+        );
+        assert_eq!(
+            parse(&["fmt", "--check", "a.lox"]).command,
+            Command::Fmt(String::from("a.lox"))
+        );
+        assert_eq!(
+            parse(&["debug", "a.lox"]).command,
+            Command::Debug(String::from("a.lox"))
+        );
+        assert_eq!(parse(&["dap"]).command, Command::Dap);
+        assert_eq!(parse(&["lsp"]).command, Command::Lsp);
+        assert_eq!(parse(&["run", "--help"]).command, Command::Help);
+    }
+
+    #[test]
+    fn options() {
+        let cli = parse(&[
+            "run",
+            "--trace",
+            "a.lox",
+            "--stack-size",
+            "8",
+            "--no-optimize",
+        ]);
+        assert_eq!(cli.trace, Some(TraceTarget::Stderr));
+        assert!(!cli.options.optimize);
+        assert_eq!(cli.options.stack_size, 8);
+
+        let cli = parse(&["--trace-file", "trace.txt", "a.lox"]);
+        assert_eq!(
+            cli.trace,
+            Some(TraceTarget::File(String::from("trace.txt")))
+        );
+    }
+
+    #[test]
+    fn script_args() {
+        let cli = parse(&["run", "a.lox", "one", "--trace", "two"]);
+        assert_eq!(cli.command, Command::Run(String::from("a.lox")));
+        assert_eq!(cli.args, ["one", "two"]);
+        assert_eq!(cli.trace, Some(TraceTarget::Stderr));
+
+        let cli = parse(&["a.lox", "--", "--trace", "-x"]);
+        assert_eq!(cli.command, Command::Run(String::from("a.lox")));
+        assert_eq!(cli.args, ["--trace", "-x"]);
+        assert_eq!(cli.trace, None);
+
+        assert_eq!(parse(&["debug", "a.lox", "one"]).args, ["one"]);
+        assert!(parse(&["run", "a.lox"]).args.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse_args(args(&["run"])).is_err());
+        assert!(parse_args(args(&["--stack-size"])).is_err());
+        assert!(parse_args(args(&["--stack-size", "many"])).is_err());
+        assert!(parse_args(args(&["--trace-file"])).is_err());
+        assert!(parse_args(args(&["--unknown"])).is_err());
+        assert!(parse_args(args(&["dis", "a.lox", "b.lox"])).is_err());
+        assert!(parse_args(args(&["repl", "a.lox"])).is_err());
+        assert!(parse_args(args(&["dis", "--bench", "a.lox"])).is_err());
+        assert!(parse_args(args(&["run", "--check", "a.lox"])).is_err());
+        assert!(parse_args(args(&["fmt", "--trivia", "a.lox"])).is_err());
+    }
+
+    fn parse(arguments: &[&str]) -> Cli {
+        parse_args(args(arguments)).unwrap()
+    }
+
+    fn args(arguments: &[&str]) -> impl Iterator<Item = String> {
+        arguments
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
     }
 }
@@ -1,4 +1,13 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    value::Value,
+    vm::{InterpretResult, RuntimeError},
+};
 
 #[derive(Debug)]
 pub struct StringObject {
@@ -6,11 +15,6 @@ pub struct StringObject {
 }
 
 impl StringObject {
-    pub fn new(str: &str) -> StringObject {
-        StringObject {
-            value: String::from(str),
-        }
-    }
     pub fn from_owned(str: String) -> StringObject {
         StringObject { value: str }
     }
@@ -21,3 +25,141 @@ impl Display for StringObject {
         f.write_str(&self.value)
     }
 }
+
+#[derive(Debug, Default)]
+pub struct ListObject {
+    pub elements: Vec<Value>,
+}
+
+impl ListObject {
+    pub fn new(elements: Vec<Value>) -> ListObject {
+        ListObject { elements }
+    }
+
+    /// The element `index` refers to, counting back from the end when it's
+    /// negative.
+    pub fn position(&self, index: &Value) -> InterpretResult<usize> {
+        let len = self.elements.len();
+        match self.offset(index)? {
+            offset if (0..len as i64).contains(&offset) => Ok(offset as usize),
+            _ => Err(RuntimeError::new(&format!(
+                "List index {index} is out of bounds for a list of length {len}."
+            ))),
+        }
+    }
+
+    /// Like `position`, but also allows the index just past the last
+    /// element.
+    pub fn insertion_point(&self, index: &Value) -> InterpretResult<usize> {
+        let len = self.elements.len();
+        match self.offset(index)? {
+            offset if (0..=len as i64).contains(&offset) => Ok(offset as usize),
+            _ => Err(RuntimeError::new(&format!(
+                "List index {index} is out of bounds for a list of length {len}."
+            ))),
+        }
+    }
+
+    /// Like `insertion_point`, but clamps indexes outside the list to its
+    /// ends instead of failing.
+    pub fn clamped(&self, index: &Value) -> InterpretResult<usize> {
+        let len = self.elements.len() as i64;
+        Ok(self.offset(index)?.clamp(0, len) as usize)
+    }
+
+    fn offset(&self, index: &Value) -> InterpretResult<i64> {
+        match index {
+            Value::Integer(i) if *i < 0 => Ok(i + self.elements.len() as i64),
+            Value::Integer(i) => Ok(*i),
+            v => Err(RuntimeError::new(&format!(
+                "List indexes must be integers but found '{v}'."
+            ))),
+        }
+    }
+}
+
+/// A value that can be used as a map key. Integers and floats that compare
+/// equal are the same key, so `1` and `1.0` find the same entry.
+#[derive(Debug, Clone)]
+pub struct MapKey(Value);
+
+impl MapKey {
+    pub fn new(value: &Value) -> InterpretResult<MapKey> {
+        match value {
+            Value::Nil | Value::Boolean(_) | Value::Integer(_) | Value::String(_) => {
+                Ok(MapKey(value.clone()))
+            }
+            Value::Number(n) if !n.is_nan() => Ok(MapKey(value.clone())),
+            v => Err(RuntimeError::new(&format!(
+                "Can't use {} '{v}' as a map key.",
+                v.type_name()
+            ))),
+        }
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for MapKey {}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Value::Nil => 0.hash(state),
+            Value::Boolean(b) => (1, b).hash(state),
+            // Numbers hash as floats, as that's how integers and floats are
+            // compared. Adding zero turns -0.0 into 0.0.
+            Value::Integer(n) => (2, (*n as f64 + 0.0).to_bits()).hash(state),
+            Value::Number(n) => (2, (n + 0.0).to_bits()).hash(state),
+            Value::String(s) => (3, &s.value).hash(state),
+            Value::List(_) | Value::Map(_) => unreachable!("not a valid key"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MapObject {
+    /// Entries in the order their keys were first inserted.
+    pub entries: Vec<(Value, Value)>,
+    /// Where each key's entry is in `entries`.
+    positions: HashMap<MapKey, usize>,
+}
+
+impl MapObject {
+    pub fn get(&self, key: &Value) -> InterpretResult<Option<&Value>> {
+        let position = self.positions.get(&MapKey::new(key)?);
+        Ok(position.map(|&position| &self.entries[position].1))
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> InterpretResult<()> {
+        let map_key = MapKey::new(&key)?;
+        match self.positions.get(&map_key) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.positions.insert(map_key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Value) -> InterpretResult<Option<Value>> {
+        let Some(removed) = self.positions.remove(&MapKey::new(key)?) else {
+            return Ok(None);
+        };
+        for position in self.positions.values_mut() {
+            if *position > removed {
+                *position -= 1;
+            }
+        }
+        Ok(Some(self.entries.remove(removed).1))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
@@ -0,0 +1,531 @@
+use std::{collections::HashSet, rc::Rc};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    objects::StringObject,
+    value::Value,
+    vm,
+};
+
+/// Peephole pass over a compiled chunk.
+///
+/// Folds constant arithmetic and string concatenation, drops constants that
+/// are popped right away, and drops `Not Not` and `Negate Negate` pairs when
+/// the value below them is already a boolean or a number that negates without
+/// overflowing. Every rewrite keeps the observable behaviour of the chunk, runtime
+/// errors included, so `--"a"` still fails when it runs.
+///
+/// Once nothing more folds, a `Constant` followed by a binary op, adding a
+/// constant to a local, and comparing two locals before a conditional jump are
+/// each fused into a single superinstruction.
+///
+/// Ops are only ever removed or merged with their neighbours, never moved past
+/// one another, so jump targets are remapped from the old offsets to the new
+/// ones in a single pass, and so are exception handlers and the ranges locals
+/// are in scope for. Nothing is merged across an op that is jumped to or that
+/// starts or ends one of those ranges, as the ops before it don't always run
+/// first.
+pub fn optimize(chunk: &Chunk) -> Chunk {
+    let targets = jump_targets(chunk);
+    let mut optimized = Chunk::new();
+    // Where each op of `chunk` ended up in `optimized`.
+    let mut offsets = Vec::with_capacity(chunk.op_count() + 1);
+    // Rewrites only look at ops from here on.
+    let mut barrier = 0;
+
+    for (offset, op) in chunk.code().iter().enumerate() {
+        let line = chunk.line(offset).unwrap_or(0);
+        if targets.contains(&offset) {
+            barrier = optimized.op_count();
+        }
+        offsets.push(optimized.op_count());
+        match op.constant() {
+            Some(constant) => {
+                let constant = optimized.add_constant(chunk.read_constant(constant).clone());
+                optimized.emit(op.with_constant(constant), line)
+            }
+            None => optimized.emit(op.clone(), line),
+        }
+        while rewrite_tail(&mut optimized, barrier) {}
+    }
+    offsets.push(optimized.op_count());
+    optimized.handlers = chunk.handlers.clone();
+    optimized.locals = chunk.locals.clone();
+    retarget(&mut optimized, &offsets);
+
+    fuse(&optimized)
+}
+
+fn jump_targets(chunk: &Chunk) -> HashSet<usize> {
+    let handlers = chunk
+        .handlers
+        .iter()
+        .flat_map(|handler| [handler.start, handler.end, handler.target]);
+    let locals = chunk
+        .locals
+        .iter()
+        .flat_map(|local| [local.start, local.end]);
+    chunk
+        .code()
+        .iter()
+        .filter_map(OpCode::jump_target)
+        .chain(handlers)
+        .chain(locals)
+        .collect()
+}
+
+/// Points every jump, handler and local's range at the new offsets of the ops
+/// they used to point at.
+fn retarget(chunk: &mut Chunk, offsets: &[usize]) {
+    for offset in 0..chunk.op_count() {
+        let op = &chunk.code()[offset];
+        if let Some(target) = op.jump_target() {
+            let op = op.with_jump_target(offsets[target]);
+            chunk.op_patch(offset, op);
+        }
+    }
+    for handler in &mut chunk.handlers {
+        handler.start = offsets[handler.start];
+        handler.end = offsets[handler.end];
+        handler.target = offsets[handler.target];
+    }
+    for local in &mut chunk.locals {
+        local.start = offsets[local.start];
+        local.end = offsets[local.end];
+    }
+}
+
+fn fuse(chunk: &Chunk) -> Chunk {
+    let targets = jump_targets(chunk);
+    let mut fused = Chunk::new();
+    fused.constants = chunk.constants.clone();
+    fused.handlers = chunk.handlers.clone();
+    fused.locals = chunk.locals.clone();
+    let mut offsets = Vec::with_capacity(chunk.op_count() + 1);
+
+    let mut offset = 0;
+    while let Some(op) = chunk.op_get(offset) {
+        let line = chunk.line(offset).unwrap_or(0);
+        offsets.push(fused.op_count());
+        // The ops after this one, up to the first that is jumped to.
+        let ahead: Vec<&OpCode> = (offset + 1..offset + 4)
+            .map_while(|offset| chunk.op_get(offset).filter(|_| !targets.contains(&offset)))
+            .collect();
+        let long = match (op, ahead.as_slice()) {
+            (
+                OpCode::GetLocal(slot),
+                [OpCode::Constant(constant), OpCode::Add, OpCode::SetLocal(set)],
+            ) if slot == set => Some(OpCode::AddLocalConstant(*slot, *constant)),
+            (
+                OpCode::GetLocal(a),
+                [OpCode::GetLocal(b), OpCode::Less, OpCode::JumpIfFalse(target)],
+            ) => Some(OpCode::LessLocalsJumpIfFalse(*a, *b, *target)),
+            _ => None,
+        };
+        if let Some(superinstruction) = long {
+            fused.emit(superinstruction, line);
+            offsets.extend([fused.op_count() - 1; 3]);
+            offset += 4;
+            continue;
+        }
+
+        let next = ahead.first().copied();
+        let superinstruction = match (op, next) {
+            (OpCode::Constant(constant), Some(OpCode::Add)) => Some(OpCode::AddConstant(*constant)),
+            (OpCode::Constant(constant), Some(OpCode::Subtract)) => {
+                Some(OpCode::SubtractConstant(*constant))
+            }
+            (OpCode::Constant(constant), Some(OpCode::Multiply)) => {
+                Some(OpCode::MultiplyConstant(*constant))
+            }
+            (OpCode::Constant(constant), Some(OpCode::Divide)) => {
+                Some(OpCode::DivideConstant(*constant))
+            }
+            _ => None,
+        };
+
+        match superinstruction {
+            Some(superinstruction) => {
+                fused.emit(superinstruction, line);
+                offsets.push(fused.op_count() - 1);
+                offset += 2;
+            }
+            None => {
+                fused.emit(op.clone(), line);
+                offset += 1;
+            }
+        }
+    }
+
+    offsets.push(fused.op_count());
+    retarget(&mut fused, &offsets);
+    fused
+}
+
+/// Tries one rewrite on the last ops of `optimized`, returning whether it
+/// changed anything so the caller can keep going until nothing applies.
+///
+/// Every op in the chunk being built owns the constant it points to, if it
+/// has one, so the constants of the ops being replaced are always the last
+/// ones in the table and can be dropped along with them.
+fn rewrite_tail(optimized: &mut Chunk, barrier: usize) -> bool {
+    let count = optimized.op_count();
+    let tail = |back: usize| {
+        count
+            .checked_sub(back)
+            .filter(|&offset| offset >= barrier)
+            .and_then(|offset| optimized.op_get(offset))
+    };
+
+    let fold = match (tail(3), tail(2), tail(1)) {
+        (Some(OpCode::Constant(a)), Some(OpCode::Constant(b)), Some(op)) => {
+            let a = optimized.read_constant(*a);
+            let b = optimized.read_constant(*b);
+            fold_binary(op, a, b).map(|value| (3, Some(value)))
+        }
+        _ => None,
+    };
+
+    let rewrite = fold.or_else(|| match (tail(3), tail(2), tail(1)) {
+        (
+            _,
+            Some(OpCode::Constant(_) | OpCode::True | OpCode::False | OpCode::Nil),
+            Some(OpCode::Pop),
+        ) => Some((2, None)),
+        (_, Some(OpCode::Constant(a)), Some(op)) => {
+            let a = optimized.read_constant(*a);
+            fold_unary(op, a).map(|value| (2, Some(value)))
+        }
+        (
+            _,
+            Some(literal @ (OpCode::True | OpCode::False | OpCode::Nil)),
+            Some(OpCode::Stringify),
+        ) => {
+            let name = match literal {
+                OpCode::True => "true",
+                OpCode::False => "false",
+                _ => "nil",
+            };
+            Some((
+                2,
+                Some(Value::String(Rc::from(StringObject::from_owned(
+                    name.to_string(),
+                )))),
+            ))
+        }
+        (_, Some(OpCode::True), Some(OpCode::Not)) => Some((2, Some(Value::Boolean(false)))),
+        (_, Some(OpCode::False | OpCode::Nil), Some(OpCode::Not)) => {
+            Some((2, Some(Value::Boolean(true))))
+        }
+        (Some(OpCode::Not), Some(OpCode::Not), Some(OpCode::Not)) => Some((2, None)),
+        // Only a negated number is sure to negate again, `i64::MIN` can't.
+        (Some(OpCode::Negate), Some(OpCode::Negate), Some(OpCode::Negate)) => Some((2, None)),
+        _ => None,
+    });
+
+    match rewrite {
+        Some((count, value)) => {
+            replace_tail(optimized, count, value);
+            true
+        }
+        None => false,
+    }
+}
+
+fn fold_binary(op: &OpCode, a: &Value, b: &Value) -> Option<Value> {
+    match (op, a, b) {
+        (OpCode::Add, Value::String(a), Value::String(b)) => Some(Value::String(Rc::from(
+            StringObject::from_owned(format!("{}{}", a.value, b.value)),
+        ))),
+        (
+            OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Modulo
+            | OpCode::Power
+            | OpCode::BitAnd
+            | OpCode::BitOr
+            | OpCode::BitXor
+            | OpCode::ShiftLeft
+            | OpCode::ShiftRight,
+            a,
+            b,
+        ) => vm::arithmetic(op, a, b).ok(),
+        _ => None,
+    }
+}
+
+fn fold_unary(op: &OpCode, a: &Value) -> Option<Value> {
+    match (op, a) {
+        (OpCode::Negate, a) => vm::negate(a).ok(),
+        (OpCode::Not, value) => Some(Value::Boolean(value.is_falsey())),
+        (OpCode::Stringify, Value::String(_)) => Some(a.clone()),
+        (OpCode::Stringify, value) => Some(Value::String(Rc::from(StringObject::from_owned(
+            value.to_string(),
+        )))),
+        _ => None,
+    }
+}
+
+/// Drops the last `count` ops and, if given, pushes a constant in their place
+/// on the line of the first dropped op.
+fn replace_tail(optimized: &mut Chunk, count: usize, value: Option<Value>) {
+    let first = optimized.op_count() - count;
+    let line = optimized.line(first).unwrap_or(0);
+    let dropped_constants = optimized.code()[first..]
+        .iter()
+        .filter(|op| op.constant().is_some())
+        .count();
+    optimized.truncate(first);
+    optimized
+        .constants
+        .truncate(optimized.constants.len() - dropped_constants);
+
+    match value {
+        Some(Value::Boolean(true)) => optimized.emit(OpCode::True, line),
+        Some(Value::Boolean(false)) => optimized.emit(OpCode::False, line),
+        Some(Value::Nil) => optimized.emit(OpCode::Nil, line),
+        Some(value) => optimized.emit_constant(value, line),
+        None => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use crate::{
+        chunk::{Chunk, OpCode},
+        compiler::Compiler,
+        value::Value,
+    };
+
+    #[test]
+    fn folds_arithmetic() {
+        let chunk = optimize(&compile("1 + 2 * 3 - -4"));
+        assert_ops(&chunk, &["Constant(_)", "Return"]);
+        assert!(matches!(folded_constant(&chunk), Value::Integer(11)));
+
+        let chunk = optimize(&compile("7 / 2 + 0.5 + (6 & 3) + (1 << 4)"));
+        assert_ops(&chunk, &["Constant(_)", "Return"]);
+        assert!(matches!(folded_constant(&chunk), Value::Number(n) if n == 21.5));
+    }
+
+    #[test]
+    fn folds_string_concatenation() {
+        let chunk = optimize(&compile("\"foo\" + \"bar\""));
+        assert_ops(&chunk, &["Constant(_)", "Return"]);
+        assert_eq!(folded_constant(&chunk).to_string(), "foobar");
+
+        let chunk = optimize(&compile("\"${1 + 2} and ${\"a\" + \"${0.5}\"}!\""));
+        assert_ops(&chunk, &["Constant(_)", "Return"]);
+        assert_eq!(folded_constant(&chunk).to_string(), "3 and a0.5!");
+
+        let chunk = optimize(&compile("\"${nil} ${true}\""));
+        assert_ops(&chunk, &["Constant(_)", "Return"]);
+        assert_eq!(folded_constant(&chunk).to_string(), "nil true");
+    }
+
+    #[test]
+    fn folds_not() {
+        assert_ops(&optimize(&compile("!!true")), &["True", "Return"]);
+        assert_ops(&optimize(&compile("!nil")), &["True", "Return"]);
+        assert_ops(&optimize(&compile("!1")), &["False", "Return"]);
+    }
+
+    #[test]
+    fn removes_unused_constants() {
+        assert_ops(
+            &optimize(&compile("1 + 2; nil; \"a\"; 3")),
+            &["Constant(_)", "Return"],
+        );
+        assert!(matches!(
+            folded_constant(&optimize(&compile("1 + 2; 3"))),
+            Value::Integer(3)
+        ));
+    }
+
+    #[test]
+    fn keeps_mismatched_operands() {
+        let chunk = optimize(&compile("1 + \"a\""));
+        assert_ops(&chunk, &["Constant(_)", "AddConstant", "Return"]);
+
+        let chunk = optimize(&compile("-\"a\""));
+        assert_ops(&chunk, &["Constant(_)", "Negate", "Return"]);
+
+        let chunk = optimize(&compile("1 / 0"));
+        assert_ops(&chunk, &["Constant(_)", "DivideConstant", "Return"]);
+    }
+
+    #[test]
+    fn fuses_constant_operands() {
+        let chunk = optimize(&compile("(1 + \"a\") * 2"));
+        assert_ops(
+            &chunk,
+            &["Constant(_)", "AddConstant", "MultiplyConstant", "Return"],
+        );
+    }
+
+    #[test]
+    fn fuses_local_sequences() {
+        let chunk = optimize(&compile(
+            "{ var i = 0; var n = 3; i += 1; i = n + 1; match (i) { _ if i < n => 1; } }",
+        ));
+        assert_ops(
+            &chunk,
+            &[
+                "Constant(_)",
+                "Constant(_)",
+                "AddLocalConstant(0, _)",
+                "Pop",
+                "GetLocal(1)",
+                "AddConstant",
+                "SetLocal(0)",
+                "Pop",
+                "GetLocal(0)",
+                "LessLocalsJumpIfFalse(0, 1, 12)",
+                "Pop",
+                "Jump(13)",
+                "Pop",
+                "Pop",
+                "Pop",
+                "Pop",
+                "Nil",
+                "Return",
+            ],
+        );
+    }
+
+    #[test]
+    fn keeps_method_names() {
+        let chunk = optimize(&compile("[1 + 2, 3].slice(0 - 1)"));
+        assert_ops(
+            &chunk,
+            &[
+                "Constant(_)",
+                "Constant(_)",
+                "BuildList(2)",
+                "Constant(_)",
+                "Invoke",
+                "Return",
+            ],
+        );
+        match chunk.code()[4] {
+            OpCode::Invoke(name, 1) => assert_eq!(chunk.read_constant(name).to_string(), "slice"),
+            ref op => panic!("Expected an invoke but found {:?}", op),
+        }
+    }
+
+    #[test]
+    fn removes_double_negation() {
+        let mut chunk = Chunk::new();
+        chunk.emit_many(&mut vec![
+            OpCode::Not,
+            OpCode::Not,
+            OpCode::Not,
+            OpCode::Negate,
+            OpCode::Negate,
+            OpCode::Negate,
+            OpCode::Return,
+        ]);
+        assert_ops(&optimize(&chunk), &["Not", "Negate", "Return"]);
+    }
+
+    #[test]
+    fn folds_around_jump_targets() {
+        let mut chunk = Chunk::new();
+        let constants: Vec<_> = (1..=3)
+            .map(|n| chunk.add_constant(Value::Integer(n)))
+            .collect();
+        chunk.emit_many(&mut vec![
+            OpCode::Constant(constants[0]),
+            OpCode::Constant(constants[1]),
+            OpCode::Add,
+            OpCode::Constant(constants[2]),
+            OpCode::Add,
+            OpCode::Pop,
+            OpCode::Jump(3),
+            OpCode::Return,
+        ]);
+        // The jump lands between the two additions, so only the first folds.
+        assert_ops(
+            &optimize(&chunk),
+            &["Constant(_)", "AddConstant", "Pop", "Jump(1)", "Return"],
+        );
+    }
+
+    #[test]
+    fn remaps_handlers() {
+        let chunk = optimize(&compile("try { x + (1 + 2); } catch (e) { e; }"));
+        let handler = &chunk.handlers[0];
+        assert_eq!((handler.start, handler.end, handler.target), (0, 3, 4));
+        assert_ops(
+            &chunk,
+            &[
+                "GetGlobal(_)",
+                "AddConstant",
+                "Pop",
+                "Jump(8)",
+                "GetLocal(0)",
+                "Pop",
+                "Pop",
+                "Jump(8)",
+                "Nil",
+                "Return",
+            ],
+        );
+    }
+
+    #[test]
+    fn keeps_double_negation_of_unknown_values() {
+        let mut chunk = Chunk::new();
+        chunk.emit_many(&mut vec![
+            OpCode::Add,
+            OpCode::Not,
+            OpCode::Not,
+            OpCode::Add,
+            OpCode::Negate,
+            OpCode::Negate,
+            OpCode::Multiply,
+            OpCode::Negate,
+            OpCode::Negate,
+        ]);
+        assert_ops(
+            &optimize(&chunk),
+            &[
+                "Add", "Not", "Not", "Add", "Negate", "Negate", "Multiply", "Negate", "Negate",
+            ],
+        );
+    }
+
+    fn compile(source: &str) -> Chunk {
+        let mut compiler = Compiler::from_source(source);
+        compiler.optimize = false;
+        compiler.compile()
+    }
+
+    fn folded_constant(chunk: &Chunk) -> Value {
+        match chunk.code().first() {
+            Some(OpCode::Constant(offset)) => chunk.read_constant(*offset).clone(),
+            op => panic!("Expected a constant but found {:?}", op),
+        }
+    }
+
+    fn assert_ops(chunk: &Chunk, expected: &[&str]) {
+        let actual: Vec<String> = chunk
+            .code()
+            .iter()
+            .map(|op| match op {
+                OpCode::Constant(_) => String::from("Constant(_)"),
+                OpCode::GetGlobal(_) => String::from("GetGlobal(_)"),
+                OpCode::AddConstant(_) => String::from("AddConstant"),
+                OpCode::MultiplyConstant(_) => String::from("MultiplyConstant"),
+                OpCode::DivideConstant(_) => String::from("DivideConstant"),
+                OpCode::Invoke(..) => String::from("Invoke"),
+                OpCode::AddLocalConstant(slot, _) => format!("AddLocalConstant({slot}, _)"),
+                op => format!("{:?}", op),
+            })
+            .collect();
+        assert_eq!(actual, expected);
+    }
+}
@@ -6,9 +6,14 @@ pub enum Precedence {
     And,        // and
     Equality,   // == !=
     Comparison, // < > <= >=
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
+    Shift,      // << >>
     Term,       // + -
-    Factor,     // * /
+    Factor,     // * / %
     Unary,      // ! -
+    Exponent,   // **
     Call,       // . ()
     Primary,
 }
@@ -24,9 +29,14 @@ impl TryFrom<i32> for Precedence {
             x if x == Precedence::And as i32 => Ok(Precedence::And),
             x if x == Precedence::Equality as i32 => Ok(Precedence::Equality),
             x if x == Precedence::Comparison as i32 => Ok(Precedence::Comparison),
+            x if x == Precedence::BitOr as i32 => Ok(Precedence::BitOr),
+            x if x == Precedence::BitXor as i32 => Ok(Precedence::BitXor),
+            x if x == Precedence::BitAnd as i32 => Ok(Precedence::BitAnd),
+            x if x == Precedence::Shift as i32 => Ok(Precedence::Shift),
             x if x == Precedence::Term as i32 => Ok(Precedence::Term),
             x if x == Precedence::Factor as i32 => Ok(Precedence::Factor),
             x if x == Precedence::Unary as i32 => Ok(Precedence::Unary),
+            x if x == Precedence::Exponent as i32 => Ok(Precedence::Exponent),
             x if x == Precedence::Call as i32 => Ok(Precedence::Call),
             x if x == Precedence::Primary as i32 => Ok(Precedence::Primary),
             _ => Err(()),
@@ -36,6 +46,16 @@ impl TryFrom<i32> for Precedence {
 
 impl Precedence {
     pub fn next(&self) -> Precedence {
-        Precedence::try_from((*self as i32 + 1).min(10)).unwrap()
+        Precedence::try_from((*self as i32 + 1).min(Precedence::Primary as i32)).unwrap()
+    }
+
+    /// The lowest precedence the right operand of an operator at this level
+    /// can have. Right associative levels accept themselves, so `a ** b ** c`
+    /// parses as `a ** (b ** c)`.
+    pub fn right_operand(&self) -> Precedence {
+        match self {
+            Precedence::Exponent => *self,
+            _ => self.next(),
+        }
     }
 }
@@ -0,0 +1,105 @@
+use std::{
+    fmt::Debug,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::Value as Json;
+
+/// Reads and writes JSON messages framed with a `Content-Length` header, the
+/// transport shared by the debug adapter and the language server.
+pub struct Connection {
+    reader: Box<dyn BufRead>,
+    writer: Box<dyn Write>,
+}
+
+impl Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Connection")
+    }
+}
+
+impl Connection {
+    pub fn new(reader: Box<dyn BufRead>, writer: Box<dyn Write>) -> Self {
+        Connection { reader, writer }
+    }
+
+    /// Reads the next message, or `None` once the other side has hung up.
+    pub fn read(&mut self) -> io::Result<Option<Json>> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.reader.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                if length.is_some() {
+                    break;
+                }
+                continue;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        let mut content = vec![0; length.unwrap_or(0)];
+        self.reader.read_exact(&mut content)?;
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn write(&mut self, message: &Json) -> io::Result<()> {
+        let content = message.to_string();
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.writer.flush()
+    }
+}
+
+/// Writer whose output can still be read once it has been boxed up and
+/// handed to a `Connection`.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct SharedBuffer(pub std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Frames `messages` the way `Connection` expects to read them.
+#[cfg(test)]
+pub fn frame(messages: &[Json]) -> Vec<u8> {
+    messages
+        .iter()
+        .flat_map(|message| {
+            let content = message.to_string();
+            format!("Content-Length: {}\r\n\r\n{}", content.len(), content).into_bytes()
+        })
+        .collect()
+}
+
+/// Reads back every message in `output`.
+#[cfg(test)]
+pub fn unframe(output: Vec<u8>) -> Vec<Json> {
+    let mut connection = Connection::new(Box::new(io::Cursor::new(output)), Box::new(io::sink()));
+    let mut messages = vec![];
+    while let Some(message) = connection.read().unwrap() {
+        messages.push(message);
+    }
+    messages
+}
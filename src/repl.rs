@@ -0,0 +1,222 @@
+use std::{env, fs, path::PathBuf, time::Instant};
+
+use rustyline::{
+    completion::Completer,
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Editor, Helper,
+};
+
+use crate::{interpreter::Interpreter, scanner::Scanner, token::TokenType};
+
+const HISTORY_FILE: &str = ".rux_history";
+
+pub fn run(mut interpreter: Interpreter) {
+    let mut editor: Editor<ReplHelper, DefaultHistory> =
+        Editor::new().expect("Failed to start the line editor");
+    editor.set_helper(Some(ReplHelper));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session.
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        match editor.readline("> ") {
+            Ok(source) => {
+                if source.trim().is_empty() {
+                    continue;
+                }
+                let _ = editor.add_history_entry(source.as_str());
+                match source.trim().strip_prefix(':') {
+                    Some(command) => meta_command(&mut interpreter, command),
+                    None => evaluate(&mut interpreter, &source),
+                }
+            }
+            // Ctrl-C drops the current input but keeps the session alive.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                println!("error: {error}");
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(error) = editor.save_history(path) {
+            println!("Could not save history to {}: {}", path.display(), error);
+        }
+    }
+}
+
+fn evaluate(interpreter: &mut Interpreter, source: &str) {
+    match interpreter.interpret(source) {
+        Ok(value) => println!("{value}"),
+        Err(error) => println!("{error}"),
+    }
+}
+
+const HELP: &str = "\
+:dis <expr>    show the compiled chunk for an expression
+:globals       list the global variables and their values
+:load <file>   run a file in the current session
+:reset         start over with a fresh VM
+:time <expr>   evaluate an expression and report how long it took
+:type <expr>   evaluate an expression and show the type of its result
+:help          show this message";
+
+fn meta_command(interpreter: &mut Interpreter, command: &str) {
+    let (name, argument) = match command.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, ""),
+    };
+
+    match name {
+        "dis" => match interpreter.compile(argument) {
+            Ok(chunk) => chunk.disassemble(argument),
+            Err(error) => println!("{error}"),
+        },
+        "globals" => print!("{}", list_globals(interpreter)),
+        "load" => match fs::read_to_string(argument) {
+            Ok(source) => evaluate(interpreter, &source),
+            Err(error) => println!("Could not read '{argument}': {error}"),
+        },
+        "reset" => interpreter.reset(),
+        "time" => {
+            let start = Instant::now();
+            evaluate(interpreter, argument);
+            println!("took {:?}", start.elapsed());
+        }
+        "type" => match interpreter.interpret(argument) {
+            Ok(value) => println!("{}", value.type_name()),
+            Err(error) => println!("{error}"),
+        },
+        "help" => println!("{HELP}"),
+        _ => println!("Unknown command ':{name}'. Try :help."),
+    }
+}
+
+fn list_globals(interpreter: &Interpreter) -> String {
+    let globals = interpreter.globals();
+    if globals.is_empty() {
+        return String::from("No globals defined.\n");
+    }
+    globals
+        .iter()
+        .map(|(name, value)| format!("{name} = {value}\n"))
+        .collect()
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Asks for more lines while the input has unclosed parens, braces, strings
+/// or block comments.
+/// Meta-commands always fit on one line.
+struct ReplHelper;
+
+impl ReplHelper {
+    fn is_complete(source: &str) -> bool {
+        if source.trim_start().starts_with(':') {
+            return true;
+        }
+
+        let mut depth = 0;
+        for token in Scanner::new(source).tokenize() {
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+                    depth -= 1
+                }
+                TokenType::Error => {
+                    if let Err(message) = &token.data {
+                        if message.starts_with("Unterminated") {
+                            return false;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        depth <= 0
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if Self::is_complete(ctx.input()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_globals, ReplHelper};
+    use crate::interpreter::{Interpreter, Options};
+
+    #[test]
+    fn complete_input() {
+        assert!(ReplHelper::is_complete("1 + 2"));
+        assert!(ReplHelper::is_complete("(1 + 2) * 3"));
+        assert!(ReplHelper::is_complete("\"(\" + \"{\""));
+        assert!(ReplHelper::is_complete("1 // (unbalanced in a comment"));
+        assert!(ReplHelper::is_complete(")"));
+        assert!(ReplHelper::is_complete(":dis (1 +"));
+    }
+
+    #[test]
+    fn globals() {
+        let mut interpreter = Interpreter::with_options(Options::default());
+        assert_eq!(list_globals(&interpreter), "No globals defined.\n");
+
+        interpreter
+            .interpret("var b = [1, \"two\"]; var a = 1 + 2; { var local = 3; }")
+            .unwrap();
+        assert_eq!(list_globals(&interpreter), "a = 3\nb = [1, \"two\"]\n");
+    }
+
+    #[test]
+    fn lines_after_errors() {
+        let mut interpreter = Interpreter::with_options(Options::default());
+        let mut line = |source| interpreter.interpret(source).map(|v| v.to_string());
+        line("var r;").unwrap();
+        assert!(line("{ var a = 1; a + nil; }").is_err());
+        line("{ var b = 5; r = b; }").unwrap();
+        assert_eq!(line("r").unwrap(), "5");
+
+        assert!(line("{ var a = 1; throw \"oops\"; }").is_err());
+        line("{ var b = 6; r = b; }").unwrap();
+        assert_eq!(line("r").unwrap(), "6");
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(!ReplHelper::is_complete("(1 + 2"));
+        assert!(!ReplHelper::is_complete("((1 +\n 2)"));
+        assert!(!ReplHelper::is_complete("{"));
+        assert!(!ReplHelper::is_complete("[1,"));
+        assert!(!ReplHelper::is_complete("\"unterminated"));
+        assert!(!ReplHelper::is_complete("1 /* unterminated"));
+    }
+}
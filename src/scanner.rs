@@ -1,5 +1,7 @@
 use crate::token::{Token, TokenResult, TokenType};
+use crate::value::Value;
 use std::{iter::Peekable, str::Chars};
+use unicode_xid::UnicodeXID;
 
 #[derive(Debug)]
 pub struct Scanner<'a> {
@@ -7,45 +9,130 @@ pub struct Scanner<'a> {
     start: usize,
     current: usize,
     line: i32,
+    /// Offset the current line starts at.
+    line_start: usize,
+    /// Line and column the token being scanned starts at.
+    token_line: i32,
+    column: i32,
     chars: Peekable<Chars<'a>>,
+    /// Produce `Comment` tokens instead of skipping comments.
+    keep_comments: bool,
+    /// Produce `Whitespace` tokens instead of skipping whitespace.
+    keep_whitespace: bool,
+    /// Braces opened inside each `${` interpolation being scanned, innermost
+    /// last. The `}` that finds none open goes back to scanning the string.
+    interpolations: Vec<usize>,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
         Scanner {
-            source: source,
+            source,
             start: 0,
             current: 0,
             line: 1,
-            chars: source.chars().into_iter().peekable(),
+            line_start: 0,
+            token_line: 1,
+            column: 1,
+            chars: source.chars().peekable(),
+            keep_comments: false,
+            keep_whitespace: false,
+            interpolations: vec![],
+        }
+    }
+
+    /// A scanner that hands out comments as tokens, for tools that need to
+    /// print the source back out.
+    pub fn with_comments(source: &'a str) -> Self {
+        Scanner {
+            keep_comments: true,
+            ..Self::new(source)
+        }
+    }
+
+    /// A scanner that loses nothing: comments and runs of whitespace come out
+    /// as tokens too, so the lexemes put together are the whole source.
+    pub fn with_trivia(source: &'a str) -> Self {
+        Scanner {
+            keep_comments: true,
+            keep_whitespace: true,
+            ..Self::new(source)
+        }
+    }
+
+    /// Every token in the source, ending with `Eof`.
+    pub fn tokenize(self) -> Tokens<'a> {
+        Tokens {
+            scanner: self,
+            done: false,
         }
     }
 
     pub fn scan_token(&mut self) -> TokenResult<'a> {
-        self.skip_whitespace();
+        if !self.keep_whitespace {
+            self.skip_whitespace();
+        }
         self.start = self.current;
+        self.token_line = self.line;
+        self.column = self.source[self.line_start..self.start].chars().count() as i32 + 1;
         match self.advance() {
             Some(c) => match c {
+                ' ' | '\t' | '\r' | '\n' if self.keep_whitespace => self.whitespace(c),
                 _ if Scanner::is_alpha(c) => self.identifier(),
                 _ if Scanner::is_digit(c) => self.number(),
 
                 // Single-char tokens
                 '(' => self.make_token(TokenType::LeftParen),
                 ')' => self.make_token(TokenType::RightParen),
-                '{' => self.make_token(TokenType::LeftBrace),
-                '}' => self.make_token(TokenType::RightBrace),
+                '{' => {
+                    if let Some(open) = self.interpolations.last_mut() {
+                        *open += 1;
+                    }
+                    self.make_token(TokenType::LeftBrace)
+                }
+                '}' if self.interpolations.last() == Some(&0) => {
+                    self.interpolations.pop();
+                    self.string()
+                }
+                '}' => {
+                    if let Some(open) = self.interpolations.last_mut() {
+                        *open -= 1;
+                    }
+                    self.make_token(TokenType::RightBrace)
+                }
+                '[' => self.make_token(TokenType::LeftBracket),
+                ']' => self.make_token(TokenType::RightBracket),
                 ';' => self.make_token(TokenType::Semicolon),
                 ',' => self.make_token(TokenType::Comma),
+                ':' => self.make_token(TokenType::Colon),
+                '.' if self.matches(&'.') => {
+                    self.make_token_if_matches(&'=', TokenType::DotDotEqual, TokenType::DotDot)
+                }
                 '.' => self.make_token(TokenType::Dot),
-                '-' => self.make_token(TokenType::Minus),
-                '+' => self.make_token(TokenType::Plus),
-                '/' => self.make_token(TokenType::Slash),
-                '*' => self.make_token(TokenType::Star),
+                '-' => self.make_token_if_matches(&'=', TokenType::MinusEqual, TokenType::Minus),
+                '+' => self.make_token_if_matches(&'=', TokenType::PlusEqual, TokenType::Plus),
+                '/' if self.keep_comments && self.peek_matches(&'/') => self.comment(),
+                '/' if self.peek_matches(&'*') => match self.block_comment() {
+                    token if self.keep_comments || token.token_type == TokenType::Error => token,
+                    _ => self.scan_token(),
+                },
+                '/' => self.make_token_if_matches(&'=', TokenType::SlashEqual, TokenType::Slash),
+                '*' if self.matches(&'*') => self.make_token(TokenType::StarStar),
+                '*' => self.make_token_if_matches(&'=', TokenType::StarEqual, TokenType::Star),
+                '%' => {
+                    self.make_token_if_matches(&'=', TokenType::PercentEqual, TokenType::Percent)
+                }
+                '&' => self.make_token(TokenType::Ampersand),
+                '|' => self.make_token(TokenType::Pipe),
+                '^' => self.make_token(TokenType::Caret),
 
                 // Two-char tokens
                 '!' => self.make_token_if_matches(&'=', TokenType::BangEqual, TokenType::Bang),
+                '=' if self.matches(&'>') => self.make_token(TokenType::EqualGreater),
                 '=' => self.make_token_if_matches(&'=', TokenType::EqualEqual, TokenType::Equal),
+                '<' if self.matches(&'<') => self.make_token(TokenType::LessLess),
                 '<' => self.make_token_if_matches(&'=', TokenType::LessEqual, TokenType::Less),
+                '>' if self.matches(&'>') => self.make_token(TokenType::GreaterGreater),
                 '>' => {
                     self.make_token_if_matches(&'=', TokenType::GreaterEqual, TokenType::Greater)
                 }
@@ -54,6 +141,10 @@ impl<'a> Scanner<'a> {
 
                 _ => self.make_error_token(&format!("Unexpected character: {}", c)),
             },
+            None if !self.interpolations.is_empty() => {
+                self.interpolations.clear();
+                self.make_error_token("Unterminated string interpolation.")
+            }
             None => self.make_eof_token(),
         }
     }
@@ -65,14 +156,15 @@ impl<'a> Scanner<'a> {
                     self.advance();
                 }
                 Some('\n') => {
-                    self.line += 1;
                     self.advance();
+                    self.new_line();
                 }
                 Some('/') => {
+                    let keep_comments = self.keep_comments;
                     let iter_save = self.chars.clone();
                     self.advance();
                     match self.peek() {
-                        Some(&'/') => {
+                        Some(&'/') if !keep_comments => {
                             self.advance();
                             loop {
                                 if self.peek_matches(&'\n') || self.is_eof() {
@@ -94,9 +186,58 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    /// Called right after consuming a newline.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    fn whitespace(&mut self, first: char) -> TokenResult<'a> {
+        if first == '\n' {
+            self.new_line();
+        }
+        while let Some(&c @ (' ' | '\t' | '\r' | '\n')) = self.peek() {
+            self.advance();
+            if c == '\n' {
+                self.new_line();
+            }
+        }
+        self.make_token(TokenType::Whitespace)
+    }
+
+    /// Block comments nest, so `/* a /* b */ c */` is a single comment.
+    fn block_comment(&mut self) -> TokenResult<'a> {
+        self.advance();
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                Some('/') if self.peek_matches(&'*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                Some('*') if self.peek_matches(&'/') => {
+                    self.advance();
+                    depth -= 1;
+                }
+                Some('\n') => self.new_line(),
+                Some(_) => (),
+                None => return self.make_error_token("Unterminated block comment."),
+            }
+        }
+        self.make_token(TokenType::Comment)
+    }
+
+    fn comment(&mut self) -> TokenResult<'a> {
+        while !self.peek_matches(&'\n') && !self.is_eof() {
+            self.advance();
+        }
+        self.make_token(TokenType::Comment)
+    }
+
     fn make_token(&self, token_type: TokenType) -> TokenResult<'a> {
         TokenResult {
-            line: self.line,
+            line: self.token_line,
+            column: self.column,
             token_type,
             data: Ok(Token {
                 start: self.start,
@@ -110,9 +251,16 @@ impl<'a> Scanner<'a> {
         let lexeme = &self.source[self.start..self.current];
         match lexeme {
             "and" => self.make_token(TokenType::And),
+            "as" => self.make_token(TokenType::As),
+            "break" => self.make_token(TokenType::Break),
+            "catch" => self.make_token(TokenType::Catch),
             "class" => self.make_token(TokenType::Class),
+            "continue" => self.make_token(TokenType::Continue),
             "else" => self.make_token(TokenType::Else),
             "if" => self.make_token(TokenType::If),
+            "import" => self.make_token(TokenType::Import),
+            "in" => self.make_token(TokenType::In),
+            "match" => self.make_token(TokenType::Match),
             "nil" => self.make_token(TokenType::Nil),
             "or" => self.make_token(TokenType::Or),
             "print" => self.make_token(TokenType::Print),
@@ -121,43 +269,84 @@ impl<'a> Scanner<'a> {
             "var" => self.make_token(TokenType::Var),
             "while" => self.make_token(TokenType::While),
             "false" => self.make_token(TokenType::False),
+            "finally" => self.make_token(TokenType::Finally),
             "for" => self.make_token(TokenType::For),
+            "from" => self.make_token(TokenType::From),
             "fun" => self.make_token(TokenType::Fun),
             "this" => self.make_token(TokenType::This),
+            "throw" => self.make_token(TokenType::Throw),
             "true" => self.make_token(TokenType::True),
-            _ => self.make_identifier_token(),
+            "try" => self.make_token(TokenType::Try),
+            _ => self.make_token(TokenType::Identifier),
         }
     }
 
     fn identifier(&mut self) -> TokenResult<'a> {
-        while self.peek_is_alpha() || self.peek_is_digit() {
+        while self
+            .peek()
+            .is_some_and(|&c| Scanner::is_identifier_continue(c))
+        {
             self.advance();
         }
         self.make_identifier_token()
     }
 
+    /// Scans anything shaped like a number, letters and all, and leaves it to
+    /// `parse_number` to say whether it is a well-formed one, so that `0x1G`
+    /// is a single malformed literal rather than a number and a name.
     fn number(&mut self) -> TokenResult<'a> {
-        while self.peek_is_digit() {
-            self.advance();
-        }
-        if self.peek_matches(&'.') {
-            self.advance();
-            while self.peek_is_digit() {
+        let radix_prefix = self.source[self.start..].starts_with('0')
+            && matches!(self.peek(), Some('x' | 'X' | 'b' | 'B'));
+        if !radix_prefix {
+            while self.peek_is_digit() || self.peek_matches(&'_') {
+                self.advance();
+            }
+            // A `..` after a number starts a range instead.
+            if self.peek_matches(&'.') && !self.source[self.current..].starts_with("..") {
                 self.advance();
+                while self.peek_is_digit() || self.peek_matches(&'_') {
+                    self.advance();
+                }
+            }
+            if self.matches(&'e') || self.matches(&'E') {
+                let _ = self.matches(&'+') || self.matches(&'-');
             }
         }
-        self.make_token(TokenType::Number)
+        while self
+            .peek()
+            .is_some_and(|&c| Scanner::is_identifier_continue(c))
+        {
+            self.advance();
+        }
+
+        match parse_number(&self.source[self.start..self.current]) {
+            Ok(_) => self.make_token(TokenType::Number),
+            Err(message) => self.make_error_token(&message),
+        }
     }
 
+    /// Scans a string, or the rest of one after an interpolation, up to its
+    /// closing quote or the next `${`. The token's lexeme keeps both ends, see
+    /// `string_contents`.
     fn string(&mut self) -> TokenResult<'a> {
-        // the first '"' of already consumed
-        self.start += 1;
-
+        // the first '"', or the '}' closing an interpolation, is already
+        // consumed
         while !self.peek_matches(&'"') && !self.is_eof() {
-            if self.peek_matches(&'\n') {
-                self.line += 1;
+            if self.source[self.current..].starts_with("${") {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                return match unescape(&self.source[self.start + 1..self.current - 2]) {
+                    Ok(_) => self.make_token(TokenType::Interpolation),
+                    Err(message) => self.make_error_token(&message),
+                };
+            }
+            match self.advance() {
+                Some('\n') => self.new_line(),
+                // Whatever follows a backslash can't end the string.
+                Some('\\') if !self.is_eof() && self.advance() == Some('\n') => self.new_line(),
+                _ => (),
             }
-            self.advance();
         }
 
         if self.is_eof() {
@@ -166,15 +355,18 @@ impl<'a> Scanner<'a> {
                 self.make_token(TokenType::String),
             ))
         } else {
-            let return_token = self.make_token(TokenType::String);
             self.advance();
-            return_token
+            match unescape(&self.source[self.start + 1..self.current - 1]) {
+                Ok(_) => self.make_token(TokenType::String),
+                Err(message) => self.make_error_token(&message),
+            }
         }
     }
 
     fn make_error_token(&self, message: &str) -> TokenResult<'a> {
         TokenResult {
-            line: self.line,
+            line: self.token_line,
+            column: self.column,
             token_type: TokenType::Error,
             data: Err(message.to_string()),
         }
@@ -182,7 +374,8 @@ impl<'a> Scanner<'a> {
 
     fn make_eof_token(&self) -> TokenResult<'a> {
         TokenResult {
-            line: self.line,
+            line: self.token_line,
+            column: self.column,
             token_type: TokenType::Eof,
             data: Ok(Token {
                 start: self.start,
@@ -207,21 +400,18 @@ impl<'a> Scanner<'a> {
 
     fn matches(&mut self, expected: &char) -> bool {
         match self.peek() {
-            Some(c) => {
-                if c == expected {
-                    self.advance();
-                    true
-                } else {
-                    false
-                }
+            Some(c) if c == expected => {
+                self.advance();
+                true
             }
-            None => false,
+            _ => false,
         }
     }
 
     fn advance(&mut self) -> Option<char> {
-        self.current += 1;
-        self.chars.next()
+        let c = self.chars.next()?;
+        self.current += c.len_utf8();
+        Some(c)
     }
 
     fn peek(&mut self) -> Option<&char> {
@@ -229,7 +419,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_eof(&mut self) -> bool {
-        self.peek() == None
+        self.peek().is_none()
     }
 
     fn peek_matches(&mut self, expected: &char) -> bool {
@@ -239,13 +429,6 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn peek_is_alpha(&mut self) -> bool {
-        match self.peek() {
-            Some(c) => Scanner::is_alpha(*c),
-            None => false,
-        }
-    }
-
     fn peek_is_digit(&mut self) -> bool {
         match self.peek() {
             Some(c) => Scanner::is_digit(*c),
@@ -253,18 +436,159 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    /// Identifiers follow Unicode's XID rules, with `_` allowed to start
+    /// one as well.
     fn is_alpha(c: char) -> bool {
-        c.is_ascii_alphabetic() || c == '_'
+        c.is_xid_start() || c == '_'
+    }
+
+    fn is_identifier_continue(c: char) -> bool {
+        c.is_xid_continue()
     }
 
     fn is_digit(c: char) -> bool {
-        c.is_digit(10)
+        c.is_ascii_digit()
+    }
+}
+
+/// The text of a `String` or `Interpolation` token between its delimiters:
+/// the opening quote, or the `}` ending the interpolation before it, and the
+/// closing quote or the `${` starting the next interpolation.
+pub fn string_contents(lexeme: &str) -> &str {
+    let rest = &lexeme[1..];
+    rest.strip_suffix("${")
+        .or_else(|| rest.strip_suffix('"'))
+        .unwrap_or(rest)
+}
+
+/// The value of a number literal: decimal with an optional fraction and
+/// exponent, like `12.5e-3`, or an integer in hex (`0x1F`) or binary
+/// (`0b1010`). Digits can be grouped with underscores, as in `1_000_000`.
+///
+/// Literals without a fraction or exponent are integers, and must fit in one.
+/// A leading `-` negates the literal rather than being part of it, so the
+/// smallest integer has to be written as `-9223372036854775807 - 1`.
+pub fn parse_number(lexeme: &str) -> Result<Value, String> {
+    let malformed = || format!("Malformed number literal '{lexeme}'.");
+
+    let radix = match lexeme.get(..2) {
+        Some("0x" | "0X") => Some(16),
+        Some("0b" | "0B") => Some(2),
+        _ => None,
+    };
+    if let Some(radix) = radix {
+        let digits = &lexeme[2..];
+        if !is_digit_group(digits, radix) {
+            return Err(malformed());
+        }
+        return i64::from_str_radix(&digits.replace('_', ""), radix)
+            .map(Value::Integer)
+            .map_err(|_| format!("Number literal '{lexeme}' is too large."));
+    }
+
+    let (mantissa, exponent) = match lexeme.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (lexeme, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let exponent_digits = exponent.map(|exponent| exponent.trim_start_matches(['+', '-']));
+    let well_formed = is_digit_group(integer, 10)
+        && fraction.is_none_or(|fraction| is_digit_group(fraction, 10))
+        && exponent_digits.is_none_or(|digits| {
+            is_digit_group(digits, 10) && exponent.unwrap().len() - digits.len() <= 1
+        });
+    if !well_formed {
+        return Err(malformed());
+    }
+    let digits = lexeme.replace('_', "");
+    if fraction.is_none() && exponent.is_none() {
+        return digits
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| format!("Number literal '{lexeme}' is too large."));
+    }
+    digits.parse().map(Value::Number).map_err(|_| malformed())
+}
+
+/// Digits in `radix`, with single underscores allowed only between them.
+fn is_digit_group(digits: &str, radix: u32) -> bool {
+    !digits.is_empty()
+        && !digits.starts_with('_')
+        && !digits.ends_with('_')
+        && !digits.contains("__")
+        && digits.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+/// The value of a string literal's source text, with its escapes replaced by
+/// what they stand for: `\n`, `\t`, `\"`, `\\`, `\$` and `\u{...}` with one
+/// to six hex digits.
+pub fn unescape(raw: &str) -> Result<String, String> {
+    let mut value = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('"') => value.push('"'),
+            Some('\\') => value.push('\\'),
+            Some('$') => value.push('$'),
+            Some('u') => value.push(unicode_escape(&mut chars)?),
+            Some(c) => return Err(format!("Invalid escape sequence '\\{c}'.")),
+            None => return Err(String::from("Unfinished escape sequence.")),
+        }
+    }
+    Ok(value)
+}
+
+fn unicode_escape(chars: &mut Chars) -> Result<char, String> {
+    let invalid = || String::from("Expected an escape like '\\u{1F600}'.");
+    if chars.next() != Some('{') {
+        return Err(invalid());
+    }
+    let mut digits = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) => digits.push(c),
+            None => return Err(invalid()),
+        }
+    }
+    if digits.is_empty() || digits.len() > 6 {
+        return Err(invalid());
+    }
+    let code = u32::from_str_radix(&digits, 16).map_err(|_| invalid())?;
+    char::from_u32(code).ok_or_else(|| format!("'\\u{{{digits}}}' is not a Unicode character."))
+}
+
+/// Iterator over the tokens of a source, see `Scanner::tokenize`.
+pub struct Tokens<'a> {
+    scanner: Scanner<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = TokenResult<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let token = self.scanner.scan_token();
+        self.done = token.token_type == TokenType::Eof;
+        Some(token)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{scanner, token::TokenType};
+    use crate::{scanner, token::TokenType, value::Value};
 
     #[test]
     fn peek() {
@@ -289,7 +613,7 @@ mod tests {
 
     #[test]
     fn error_source() {
-        assert_error_token(String::from("%"));
+        assert_error_token(String::from("$"));
         assert_error_token(String::from("@"));
     }
 
@@ -298,9 +622,13 @@ mod tests {
         assert_token(String::from(""), TokenType::Eof);
         assert_token(String::from("("), TokenType::LeftParen);
         assert_token(String::from("}"), TokenType::RightBrace);
+        assert_token(String::from("["), TokenType::LeftBracket);
+        assert_token(String::from(":"), TokenType::Colon);
         assert_token(String::from("-"), TokenType::Minus);
         assert_token(String::from("+"), TokenType::Plus);
         assert_token(String::from("/"), TokenType::Slash);
+        assert_token(String::from("%"), TokenType::Percent);
+        assert_token(String::from("&"), TokenType::Ampersand);
     }
 
     #[test]
@@ -309,6 +637,15 @@ mod tests {
         assert_token(String::from("!="), TokenType::BangEqual);
         assert_token(String::from(">"), TokenType::Greater);
         assert_token(String::from(">="), TokenType::GreaterEqual);
+        assert_token(String::from("<<"), TokenType::LessLess);
+        assert_token(String::from(">>"), TokenType::GreaterGreater);
+        assert_token(String::from("**"), TokenType::StarStar);
+        assert_token(String::from("+="), TokenType::PlusEqual);
+        assert_token(String::from("/="), TokenType::SlashEqual);
+        assert_token(String::from("%="), TokenType::PercentEqual);
+        assert_token(String::from("=>"), TokenType::EqualGreater);
+        assert_token(String::from(".."), TokenType::DotDot);
+        assert_token(String::from("..="), TokenType::DotDotEqual);
     }
 
     #[test]
@@ -318,6 +655,10 @@ mod tests {
             String::from("==="),
             &vec![TokenType::EqualEqual, TokenType::Equal],
         );
+        assert_tokens(
+            String::from("1..2.5"),
+            &vec![TokenType::Number, TokenType::DotDot, TokenType::Number],
+        );
         assert_tokens(
             String::from("()\n{}"),
             &vec![
@@ -341,12 +682,188 @@ mod tests {
         );
     }
 
+    #[test]
+    fn kept_comments() {
+        let source = "1 // one\n// two\n/ 2";
+        let mut scanner = scanner::Scanner::with_comments(source);
+        let mut tokens = vec![];
+        loop {
+            let token = scanner.scan_token();
+            if token.token_type == TokenType::Eof {
+                break;
+            }
+            tokens.push((token.token_type, token.data.unwrap().lexeme));
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Number, "1"),
+                (TokenType::Comment, "// one"),
+                (TokenType::Comment, "// two"),
+                (TokenType::Slash, "/"),
+                (TokenType::Number, "2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn columns() {
+        let positions: Vec<(i32, i32)> = scanner::Scanner::new("1 +\n  (2)\n\"a\nb\" x")
+            .tokenize()
+            .map(|token| (token.line, token.column))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (1, 1),
+                (1, 3),
+                (2, 3),
+                (2, 4),
+                (2, 5),
+                (3, 1),
+                (4, 4),
+                (4, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn trivia() {
+        let source = "1 +  2 // sum\n\t!x";
+        let tokens: Vec<(TokenType, &str)> = scanner::Scanner::with_trivia(source)
+            .tokenize()
+            .map(|token| (token.token_type, token.data.unwrap().lexeme))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Number, "1"),
+                (TokenType::Whitespace, " "),
+                (TokenType::Plus, "+"),
+                (TokenType::Whitespace, "  "),
+                (TokenType::Number, "2"),
+                (TokenType::Whitespace, " "),
+                (TokenType::Comment, "// sum"),
+                (TokenType::Whitespace, "\n\t"),
+                (TokenType::Bang, "!"),
+                (TokenType::Identifier, "x"),
+                (TokenType::Eof, ""),
+            ]
+        );
+        let lexemes: String = tokens.iter().map(|(_, lexeme)| *lexeme).collect();
+        assert_eq!(lexemes, source);
+
+        let source = "\"a\\\"\" + \"${ x } and ${\"${y}\"}\" /* \"no\" */ \"\"";
+        let lexemes: String = scanner::Scanner::with_trivia(source)
+            .tokenize()
+            .map(|token| token.data.unwrap().lexeme)
+            .collect();
+        assert_eq!(lexemes, source);
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_tokens(String::from("/* one */ +"), &vec![TokenType::Plus]);
+        assert_tokens(
+            String::from("/* a /* nested */ comment\n*/ - /**/ /"),
+            &vec![TokenType::Minus, TokenType::Slash],
+        );
+        assert_error_token(String::from("/* a /* b */"));
+
+        let mut scanner = scanner::Scanner::with_comments("/* a\n/* b */ */\n1");
+        let comment = scanner.scan_token();
+        assert_eq!(comment.token_type, TokenType::Comment);
+        assert_eq!(comment.data.unwrap().lexeme, "/* a\n/* b */ */");
+        assert_eq!(scanner.scan_token().line, 3);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            scanner::unescape(r#"a\tb\n\"c\" \\ \u{48}\u{1F600}"#),
+            Ok(String::from("a\tb\n\"c\" \\ H\u{1F600}"))
+        );
+        assert!(scanner::unescape(r"\q").is_err());
+        assert!(scanner::unescape(r"\u{110000}").is_err());
+        assert!(scanner::unescape(r"\u{12").is_err());
+        assert!(scanner::unescape(r"\u{}").is_err());
+
+        assert_token_lexeme(
+            String::from(r#""say \"hi\"""#),
+            TokenType::String,
+            r#""say \"hi\"""#,
+        );
+        assert_token_lexeme(String::from(r#""\\""#), TokenType::String, r#""\\""#);
+        assert_error_token(String::from(r#""bad \x""#));
+    }
+
+    #[test]
+    fn interpolation() {
+        let source = r#""a ${b + "c ${d}"} {${ {} }}" "\${e}""#;
+        let tokens: Vec<_> = scanner::Scanner::new(source)
+            .tokenize()
+            .map(|token| (token.token_type, token.data.unwrap().lexeme))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Interpolation, "\"a ${"),
+                (TokenType::Identifier, "b"),
+                (TokenType::Plus, "+"),
+                (TokenType::Interpolation, "\"c ${"),
+                (TokenType::Identifier, "d"),
+                (TokenType::String, "}\""),
+                (TokenType::Interpolation, "} {${"),
+                (TokenType::LeftBrace, "{"),
+                (TokenType::RightBrace, "}"),
+                (TokenType::String, "}}\""),
+                (TokenType::String, r#""\${e}""#),
+                (TokenType::Eof, ""),
+            ]
+        );
+
+        let mut scanner = scanner::Scanner::new(r#""a ${b"#);
+        assert_eq!(scanner.scan_token().token_type, TokenType::Interpolation);
+        assert_eq!(scanner.scan_token().token_type, TokenType::Identifier);
+        assert_eq!(
+            scanner.scan_token().data.unwrap_err(),
+            "Unterminated string interpolation."
+        );
+        assert_eq!(scanner.scan_token().token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn unicode() {
+        assert_token_lexeme(String::from("größe"), TokenType::Identifier, "größe");
+        assert_token_lexeme(String::from("_π2"), TokenType::Identifier, "_π2");
+        assert_token_lexeme(
+            String::from("\"héllo ✓\""),
+            TokenType::String,
+            "\"héllo ✓\"",
+        );
+        assert_error_token(String::from("✓"));
+
+        let tokens: Vec<_> = scanner::Scanner::new("ü + \"€\" // ñ\nλ")
+            .tokenize()
+            .map(|token| (token.column, token.data.unwrap().lexeme))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![(1, "ü"), (3, "+"), (5, "\"€\""), (1, "λ"), (2, "")]
+        );
+    }
+
     #[test]
     fn strings() {
-        assert_token_lexeme(String::from("\"pepe\""), TokenType::String, "pepe");
-        assert_token_lexeme(String::from("\"pepe\"\n"), TokenType::String, "pepe");
-        assert_token_lexeme(String::from("\"pepe\"\n\n"), TokenType::String, "pepe");
-        assert_token_lexeme(String::from("\"\""), TokenType::String, "");
+        assert_token_lexeme(String::from("\"pepe\""), TokenType::String, "\"pepe\"");
+        assert_token_lexeme(String::from("\"pepe\"\n"), TokenType::String, "\"pepe\"");
+        assert_token_lexeme(String::from("\"pepe\"\n\n"), TokenType::String, "\"pepe\"");
+        assert_token_lexeme(String::from("\"\""), TokenType::String, "\"\"");
+
+        assert_eq!(scanner::string_contents("\"pepe\""), "pepe");
+        assert_eq!(scanner::string_contents("\"a ${"), "a ");
+        assert_eq!(scanner::string_contents("} b ${"), " b ");
+        assert_eq!(scanner::string_contents("}\""), "");
     }
 
     #[test]
@@ -355,19 +872,62 @@ mod tests {
         assert_token_lexeme(String::from("4"), TokenType::Number, "4");
         assert_token_lexeme(String::from("42"), TokenType::Number, "42");
         assert_token_lexeme(String::from("13.99"), TokenType::Number, "13.99");
+        assert_token_lexeme(String::from("0x1F"), TokenType::Number, "0x1F");
+        assert_token_lexeme(String::from("0b1010"), TokenType::Number, "0b1010");
+        assert_token_lexeme(String::from("1e-9"), TokenType::Number, "1e-9");
+        assert_token_lexeme(String::from("1_000_000"), TokenType::Number, "1_000_000");
+        assert_tokens(
+            String::from("0x1e-5"),
+            &vec![TokenType::Number, TokenType::Minus, TokenType::Number],
+        );
+
+        assert_error_token(String::from("1."));
+        assert_error_token(String::from("0x"));
+        assert_error_token(String::from("0b102"));
+        assert_error_token(String::from("1e"));
+        assert_error_token(String::from("1_"));
+        assert_error_token(String::from("1__0"));
+        assert_error_token(String::from("0x1__F"));
+        assert_error_token(String::from("1.5_"));
+        assert_error_token(String::from("12abc"));
+    }
+
+    #[test]
+    fn number_values() {
+        assert!(matches!(
+            scanner::parse_number("0x1F"),
+            Ok(Value::Integer(31))
+        ));
+        assert!(matches!(
+            scanner::parse_number("0B1010"),
+            Ok(Value::Integer(10))
+        ));
+        assert!(matches!(
+            scanner::parse_number("1_000"),
+            Ok(Value::Integer(1000))
+        ));
+        assert!(matches!(scanner::parse_number("1e-9"), Ok(Value::Number(n)) if n == 1e-9));
+        assert!(matches!(scanner::parse_number("2.5E+3"), Ok(Value::Number(n)) if n == 2500.0));
+        assert!(
+            matches!(scanner::parse_number("1_000.000_1"), Ok(Value::Number(n)) if n == 1000.0001)
+        );
+        assert!(scanner::parse_number("1e+-2").is_err());
+        assert!(scanner::parse_number("1._5").is_err());
+        assert!(scanner::parse_number("0x8000_0000_0000_0000").is_err());
+        assert!(scanner::parse_number("9223372036854775808").is_err());
     }
 
     #[test]
     fn identifier() {
         assert_token(String::from("class"), TokenType::Class);
-        // assert_token(String::from("if"), TokenType::If);
-        // assert_token(String::from("while"), TokenType::While);
-        // assert_token(String::from("true"), TokenType::True);
-        // assert_token(String::from("false"), TokenType::False);
-
-        // assert_token_lexeme(String::from("pepe"), TokenType::Identifier, "pepe");
-        // assert_token_lexeme(String::from("for1"), TokenType::Identifier, "for1");
-        // assert_token_lexeme(String::from("whiles"), TokenType::Identifier, "whiles");
+        assert_token(String::from("if"), TokenType::If);
+        assert_token(String::from("while"), TokenType::While);
+        assert_token(String::from("true"), TokenType::True);
+        assert_token(String::from("false"), TokenType::False);
+
+        assert_token_lexeme(String::from("pepe"), TokenType::Identifier, "pepe");
+        assert_token_lexeme(String::from("for1"), TokenType::Identifier, "for1");
+        assert_token_lexeme(String::from("whiles"), TokenType::Identifier, "whiles");
     }
 
     fn assert_token(source: String, expected_type: TokenType) {
@@ -1,24 +1,37 @@
 use crate::{
-    objects::StringObject,
     value::Value,
     vm::{InterpretResult, RuntimeError},
 };
-use std::{
-    fmt::{Display, Formatter, Result},
-    rc::Rc,
-};
+use std::fmt::{Display, Formatter, Result};
+pub const DEFAULT_STACK_SIZE: usize = 16384;
+
 #[derive(Debug)]
 pub struct Stack {
     values: Vec<Value>,
+    limit: usize,
 }
 
 impl Stack {
     pub fn new() -> Self {
-        Stack { values: vec![] }
+        Self::with_limit(DEFAULT_STACK_SIZE)
+    }
+
+    pub fn with_limit(limit: usize) -> Self {
+        Stack {
+            values: vec![],
+            limit,
+        }
     }
 
-    pub fn push(&mut self, value: Value) {
-        self.values.push(value)
+    pub fn push(&mut self, value: Value) -> InterpretResult<()> {
+        if self.values.len() >= self.limit {
+            return Err(RuntimeError::new(&format!(
+                "Stack overflow, the stack is limited to {} values.",
+                self.limit
+            )));
+        }
+        self.values.push(value);
+        Ok(())
     }
 
     pub fn pop(&mut self) -> InterpretResult<Value> {
@@ -27,26 +40,43 @@ impl Stack {
             .ok_or(RuntimeError::new("Can't pop emty stack"))
     }
 
-    pub fn pop_number(&mut self) -> InterpretResult<f64> {
-        match self.pop()? {
-            Value::Number(n) => Ok(n),
-            v => Err(RuntimeError::new(&format!(
-                "Expected to pop a number but found '{}'.\n{}",
-                v, self
+    /// Pops the top `count` values, in the order they were pushed.
+    pub fn pop_many(&mut self, count: usize) -> InterpretResult<Vec<Value>> {
+        match self.values.len().checked_sub(count) {
+            Some(start) => Ok(self.values.split_off(start)),
+            None => Err(RuntimeError::new(&format!(
+                "Can't pop {count} values off a stack of {}",
+                self.values.len()
             ))),
         }
     }
 
-    pub fn pop_string(&mut self) -> InterpretResult<Rc<StringObject>> {
-        match self.pop()? {
-            Value::String(s) => Ok(s),
-            v => Err(RuntimeError::new(&format!(
-                "Expected to pop a string but found '{}'.\n{}",
-                v, self
-            ))),
+    /// The value in `slot`, counting from the bottom of the stack.
+    pub fn get(&self, slot: usize) -> InterpretResult<&Value> {
+        self.values
+            .get(slot)
+            .ok_or_else(|| RuntimeError::new(&format!("No value in stack slot {slot}")))
+    }
+
+    pub fn set(&mut self, slot: usize, value: Value) -> InterpretResult<()> {
+        match self.values.get_mut(slot) {
+            Some(old) => {
+                *old = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::new(&format!("No value in stack slot {slot}"))),
         }
     }
 
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Drops every value above the first `len`.
+    pub fn truncate(&mut self, len: usize) {
+        self.values.truncate(len);
+    }
+
     pub fn peek(&mut self) -> InterpretResult<&Value> {
         self.values
             .last()
@@ -54,13 +84,13 @@ impl Stack {
     }
 
     pub fn contents(&self) -> &Vec<Value> {
-        &&self.values
+        &self.values
     }
 }
 
 impl Display for Stack {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        if self.contents().len() == 0 {
+        if self.contents().is_empty() {
             f.write_str("        <empty stack>\n")?;
         } else {
             for (i, val) in self.values.iter().enumerate() {
@@ -1,6 +1,8 @@
 #[derive(Clone, Debug)]
 pub struct TokenResult<'a> {
+    /// Line and column the token starts at, both counting from 1.
     pub line: i32,
+    pub column: i32,
     pub token_type: TokenType,
     pub data: Result<Token<'a>, String>,
 }
@@ -9,6 +11,7 @@ impl<'a> TokenResult<'a> {
     pub fn invalid() -> Self {
         TokenResult {
             line: -1,
+            column: -1,
             token_type: TokenType::Error,
             data: Err(String::from("Invalid")),
         }
@@ -16,6 +19,7 @@ impl<'a> TokenResult<'a> {
 }
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct Token<'a> {
     pub start: usize,
     pub stop: usize,
@@ -29,13 +33,20 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
 
     // one or two character tokens.
     Bang,
@@ -46,30 +57,56 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    StarStar,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    DotDot,
+    DotDotEqual,
+    EqualGreater,
 
     // literals.
     Identifier,
     String,
+    /// The part of a string before a `${`, or between a `}` and the next `${`.
+    Interpolation,
     Number,
 
     // keywords.
     And,
+    As,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     For,
+    From,
     Fun,
     If,
+    Import,
+    In,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
+    Comment,
+    Whitespace,
     Error,
     Eof,
 }
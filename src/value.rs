@@ -1,28 +1,125 @@
-use std::{fmt::Display, rc::Rc};
+use std::{cell::RefCell, fmt::Display, rc::Rc};
 
-use crate::objects::StringObject;
+use crate::objects::{ListObject, MapObject, StringObject};
 
 #[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Boolean(bool),
     Number(f64),
+    Integer(i64),
 
     String(Rc<StringObject>),
+    List(Rc<RefCell<ListObject>>),
+    Map(Rc<RefCell<MapObject>>),
 }
 
+/// Identifies a collection while it is being printed or compared, to notice
+/// when it turns up inside itself.
+type Address = *const ();
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_nested(f, &mut vec![])
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Integer(_) => "integer",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    /// Writes the value inside the collections in `open`. A list or map
+    /// inside itself is written as `[...]` or `{...}` instead of recursing
+    /// forever.
+    fn fmt_nested(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        open: &mut Vec<Address>,
+    ) -> std::fmt::Result {
         match self {
             Value::Nil => f.write_str("nil"),
             Value::Boolean(b) => f.write_str(&b.to_string()),
             Value::Number(n) => f.write_str(&n.to_string()),
+            Value::Integer(n) => f.write_str(&n.to_string()),
             Value::String(s) => f.write_str(&s.value),
+            Value::List(list) => {
+                let address = Rc::as_ptr(list) as Address;
+                if open.contains(&address) {
+                    return f.write_str("[...]");
+                }
+                open.push(address);
+                f.write_str("[")?;
+                for (i, element) in list.borrow().elements.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    element.fmt_element(f, open)?;
+                }
+                open.pop();
+                f.write_str("]")
+            }
+            Value::Map(map) => {
+                let address = Rc::as_ptr(map) as Address;
+                if open.contains(&address) {
+                    return f.write_str("{...}");
+                }
+                open.push(address);
+                f.write_str("{")?;
+                for (i, (key, value)) in map.borrow().entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    key.fmt_element(f, open)?;
+                    f.write_str(": ")?;
+                    value.fmt_element(f, open)?;
+                }
+                open.pop();
+                f.write_str("}")
+            }
+        }
+    }
+
+    /// Writes the value as an element of a collection, where strings are
+    /// quoted so `["1"]` and `[1]` look different.
+    fn fmt_element(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        open: &mut Vec<Address>,
+    ) -> std::fmt::Result {
+        match self {
+            Value::String(s) => {
+                f.write_str("\"")?;
+                for c in s.value.chars() {
+                    match c {
+                        // Only double quotes need escaping between them.
+                        '\'' => f.write_str("'")?,
+                        c => write!(f, "{}", c.escape_debug())?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            value => value.fmt_nested(f, open),
+        }
+    }
+
+    /// The value as a float, if it's a number of either kind.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Integer(n) => Some(*n as f64),
+            _ => None,
         }
     }
-}
 
-impl Value {
     pub fn is_falsey(&self) -> bool {
         match self {
             Value::Boolean(b) => !b,
@@ -34,11 +131,59 @@ impl Value {
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut vec![])
+    }
+}
+
+impl Value {
+    /// Compares the values inside the pairs of collections in `compared`.
+    /// Meeting one of those pairs again means both contain themselves in the
+    /// same place, and nothing else found so far tells them apart, so they
+    /// are taken to be equal there.
+    fn equals(&self, other: &Self, compared: &mut Vec<(Address, Address)>) -> bool {
         match (self, other) {
             (Self::Nil, Self::Nil) => true,
             (Self::Boolean(l), Self::Boolean(r)) => l == r,
             (Self::Number(l), Self::Number(r)) => l == r,
+            (Self::Integer(l), Self::Integer(r)) => l == r,
+            // Integers and floats compare by value, like the arithmetic
+            // between them.
+            (Self::Integer(l), Self::Number(r)) | (Self::Number(r), Self::Integer(l)) => {
+                *l as f64 == *r
+            }
             (Self::String(l), Self::String(r)) => l.value == r.value,
+            (Self::List(l), Self::List(r)) => {
+                let pair = (Rc::as_ptr(l) as Address, Rc::as_ptr(r) as Address);
+                if Rc::ptr_eq(l, r) || compared.contains(&pair) {
+                    return true;
+                }
+                compared.push(pair);
+                let (l, r) = (l.borrow(), r.borrow());
+                let equal = l.elements.len() == r.elements.len()
+                    && l.elements
+                        .iter()
+                        .zip(&r.elements)
+                        .all(|(l, r)| l.equals(r, compared));
+                compared.pop();
+                equal
+            }
+            // Maps are equal when they have the same entries, in any order.
+            (Self::Map(l), Self::Map(r)) => {
+                let pair = (Rc::as_ptr(l) as Address, Rc::as_ptr(r) as Address);
+                if Rc::ptr_eq(l, r) || compared.contains(&pair) {
+                    return true;
+                }
+                compared.push(pair);
+                let (l, r) = (l.borrow(), r.borrow());
+                let equal = l.len() == r.len()
+                    && l.entries.iter().all(|(key, value)| {
+                        r.get(key).is_ok_and(|other| {
+                            other.is_some_and(|other| value.equals(other, compared))
+                        })
+                    });
+                compared.pop();
+                equal
+            }
             _ => false,
         }
     }
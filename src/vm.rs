@@ -1,8 +1,18 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::Write;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::{fmt::Display, ops::Neg};
 
+use crate::builtins;
 use crate::chunk::{Chunk, OpCode};
-use crate::objects::StringObject;
+use crate::compiler::Compiler;
+use crate::debugger::{DebugAction, DebugHook, ExecutionState};
+use crate::objects::{ListObject, MapObject, StringObject};
 use crate::stack::Stack;
 use crate::value::Value;
 
@@ -26,6 +36,15 @@ pub type InterpretResult<T> = Result<T, RuntimeError>;
 pub enum RuntimeError {
     NoMoreOperations(usize),
     Other(String),
+    /// A value thrown by `throw` that nothing caught.
+    Thrown(Value),
+    /// An error nothing caught, with the line it happened on and the file
+    /// that line is in, if the code was read from one.
+    Located {
+        error: Box<RuntimeError>,
+        file: Option<PathBuf>,
+        line: u32,
+    },
 }
 
 impl RuntimeError {
@@ -42,101 +61,643 @@ impl Display for RuntimeError {
                 ip
             )),
             RuntimeError::Other(str) => f.write_str(str),
+            RuntimeError::Thrown(value) => match error_message(value) {
+                // Rethrown runtime errors read the same as they did at first.
+                Some(message) => f.write_str(&message),
+                None => write!(f, "Uncaught exception: {value}"),
+            },
+            RuntimeError::Located { error, .. } => error.fmt(f),
         }
     }
 }
 
+/// Counters collected while running, reported by `--bench`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub instructions: u64,
+    pub allocations: u64,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "instructions: {}", self.instructions)?;
+        write!(f, "allocations: {}", self.allocations)
+    }
+}
+
+/// Where the VM writes its execution trace.
+pub struct Tracer(pub Box<dyn Write>);
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
 #[derive(Debug)]
 pub struct VM {
     pub stack: Stack,
+    pub stats: Stats,
+    /// When set, the stack and every instruction are written here before the
+    /// instruction runs.
+    pub trace: Option<Tracer>,
+    /// When set, asked before every instruction whether to keep going.
+    pub debugger: Option<Box<dyn DebugHook>>,
+    /// Globals of the module being run.
+    pub globals: HashMap<String, Value>,
+    /// File of the module being run, which imports are relative to. Imports
+    /// from a script without one are relative to the working directory.
+    pub path: Option<PathBuf>,
+    /// Run the peephole optimizer over imported modules.
+    pub optimize: bool,
+    /// Every module imported so far, by their canonical path.
+    modules: HashMap<PathBuf, Value>,
+    /// Modules being run, outermost first, to catch import cycles.
+    importing: Vec<PathBuf>,
 }
 
 impl VM {
     pub fn new() -> Self {
         VM {
             stack: Stack::new(),
+            stats: Stats::default(),
+            trace: None,
+            debugger: None,
+            globals: HashMap::new(),
+            path: None,
+            optimize: true,
+            modules: HashMap::new(),
+            importing: vec![],
         }
     }
 
-    pub fn run_main(&mut self, function: &Chunk) -> InterpretResult<()> {
+    pub fn run_main(&mut self, function: &Chunk) -> InterpretResult<Value> {
+        // A run that failed leaves its values behind, and the script's
+        // locals are numbered from the bottom of the stack.
+        self.stack.truncate(0);
+        self.importing = self.path.iter().cloned().collect();
         self.run(function)
     }
 
-    pub fn run(&mut self, function: &Chunk) -> InterpretResult<()> {
-        let mut frame = CallFrame::new(&function);
+    pub fn run(&mut self, function: &Chunk) -> InterpretResult<Value> {
+        let mut frame = CallFrame::new(function);
         let code = function.code();
         loop {
             let op = code
                 .get(frame.ip)
                 .ok_or(RuntimeError::NoMoreOperations(frame.ip))?;
 
-            // for val in self.stack.contents() {
-            //     println!("[ {val} ]");
-            // }
+            if let Some(Tracer(sink)) = &mut self.trace {
+                VM::trace(sink, &self.stack, frame.function, frame.ip);
+            }
+
+            if let Some(debugger) = &mut self.debugger {
+                let state = ExecutionState {
+                    chunk: frame.function,
+                    ip: frame.ip,
+                    stack: &self.stack,
+                    globals: &self.globals,
+                    file: self.path.as_deref(),
+                    // Only the script's own frame exists until there are functions.
+                    depth: 1,
+                };
+                if debugger.before_instruction(&state) == DebugAction::Stop {
+                    return Err(RuntimeError::new("Stopped by the debugger."));
+                }
+            }
 
             frame.ip += 1;
+            self.stats.instructions += 1;
 
-            match op {
-                OpCode::Return => {
-                    println!("{}", self.stack.pop().unwrap());
-                    return Ok(());
+            match self.step(&mut frame, op) {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => (),
+                Err(error) => self.catch(&mut frame, error)?,
+            }
+        }
+    }
+
+    /// Runs a single op, returning the script's result once it returns.
+    fn step(&mut self, frame: &mut CallFrame, op: &OpCode) -> InterpretResult<Option<Value>> {
+        match op {
+            OpCode::Return => return self.stack.pop().map(Some),
+            OpCode::Constant(iid) => {
+                let constant = frame.function.read_constant(*iid);
+                self.stack.push(constant.clone())?;
+            }
+            OpCode::Nil => self.stack.push(Value::Nil)?,
+            OpCode::True => self.stack.push(Value::Boolean(true))?,
+            OpCode::False => self.stack.push(Value::Boolean(false))?,
+            OpCode::Negate => {
+                let negated = negate(&self.stack.pop()?)?;
+                self.stack.push(negated)?;
+            }
+            OpCode::Add => self.add()?,
+            OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Modulo
+            | OpCode::Power
+            | OpCode::BitAnd
+            | OpCode::BitOr
+            | OpCode::BitXor
+            | OpCode::ShiftLeft
+            | OpCode::ShiftRight => VM::binary(&mut self.stack, op)?,
+            OpCode::AddConstant(iid) => {
+                self.stack
+                    .push(frame.function.read_constant(*iid).clone())?;
+                self.add()?
+            }
+            OpCode::SubtractConstant(iid) => {
+                self.stack
+                    .push(frame.function.read_constant(*iid).clone())?;
+                VM::binary(&mut self.stack, &OpCode::Subtract)?
+            }
+            OpCode::MultiplyConstant(iid) => {
+                self.stack
+                    .push(frame.function.read_constant(*iid).clone())?;
+                VM::binary(&mut self.stack, &OpCode::Multiply)?
+            }
+            OpCode::DivideConstant(iid) => {
+                self.stack
+                    .push(frame.function.read_constant(*iid).clone())?;
+                VM::binary(&mut self.stack, &OpCode::Divide)?
+            }
+            OpCode::AddLocalConstant(slot, iid) => {
+                let value = self.stack.get(*slot)?.clone();
+                self.stack.push(value)?;
+                self.stack
+                    .push(frame.function.read_constant(*iid).clone())?;
+                self.add()?;
+                let value = self.stack.peek()?.clone();
+                self.stack.set(*slot, value)?;
+            }
+            OpCode::LessLocalsJumpIfFalse(a, b, target) => {
+                let less =
+                    compare(self.stack.get(*a)?, self.stack.get(*b)?)? == Some(Ordering::Less);
+                self.stack.push(Value::Boolean(less))?;
+                if !less {
+                    frame.ip = *target;
                 }
-                OpCode::Constant(iid) => {
-                    let constant = frame.function.read_constant(*iid);
-                    self.stack.push(constant.clone());
+            }
+            OpCode::Stringify => {
+                let value = self.stack.pop()?;
+                let string = match value {
+                    Value::String(_) => value,
+                    value => {
+                        self.stats.allocations += 1;
+                        Value::String(Rc::from(StringObject::from_owned(value.to_string())))
+                    }
+                };
+                self.stack.push(string)?;
+            }
+            OpCode::BuildList(count) => {
+                let elements = self.stack.pop_many(*count)?;
+                self.stats.allocations += 1;
+                self.stack
+                    .push(Value::List(Rc::new(RefCell::new(ListObject::new(
+                        elements,
+                    )))))?;
+            }
+            OpCode::BuildMap(count) => {
+                let values = self.stack.pop_many(count * 2)?;
+                let mut map = MapObject::default();
+                for entry in values.chunks(2) {
+                    map.insert(entry[0].clone(), entry[1].clone())?;
                 }
-                OpCode::Nil => self.stack.push(Value::Nil),
-                OpCode::True => self.stack.push(Value::Boolean(true)),
-                OpCode::False => self.stack.push(Value::Boolean(false)),
-                OpCode::Negate => {
-                    let n = self.stack.pop_number()?;
-                    self.stack.push(Value::Number(n.neg()));
+                self.stats.allocations += 1;
+                self.stack.push(Value::Map(Rc::new(RefCell::new(map))))?;
+            }
+            OpCode::Index => {
+                let index = self.stack.pop()?;
+                let target = self.stack.pop()?;
+                self.stack.push(builtins::get_index(&target, &index)?)?;
+            }
+            OpCode::SetIndex => {
+                let value = self.stack.pop()?;
+                let index = self.stack.pop()?;
+                let target = self.stack.pop()?;
+                builtins::set_index(&target, &index, value.clone())?;
+                self.stack.push(value)?;
+            }
+            OpCode::DuplicatePair => {
+                let pair = self.stack.pop_many(2)?;
+                for value in pair.iter().chain(&pair) {
+                    self.stack.push(value.clone())?;
                 }
-                OpCode::Add => match self.stack.peek()? {
-                    Value::Number(_) => VM::binary(&mut self.stack, |a, b| Value::Number(a + b))?,
-                    Value::String(_) => {
-                        let b = self.stack.pop_string()?;
-                        let a = self.stack.pop_string()?;
-                        self.stack
-                            .push(Value::String(Rc::from(StringObject::from_owned(format!(
-                                "{}{}",
-                                a.value, b.value
-                            )))));
+            }
+            OpCode::Invoke(name, count) => {
+                let args = self.stack.pop_many(*count)?;
+                let receiver = self.stack.pop()?;
+                let name = frame.function.read_constant(*name).to_string();
+                self.stack.push(builtins::invoke(&receiver, &name, args)?)?;
+            }
+            OpCode::Pop => {
+                self.stack.pop()?;
+            }
+            OpCode::DefineGlobal(name) => {
+                let name = frame.function.read_constant(*name).to_string();
+                let value = self.stack.pop()?;
+                self.globals.insert(name, value);
+            }
+            OpCode::GetGlobal(name) => {
+                let name = frame.function.read_constant(*name).to_string();
+                match self.globals.get(&name) {
+                    Some(value) => self.stack.push(value.clone())?,
+                    None => return Err(VM::undefined_variable(&name)),
+                }
+            }
+            OpCode::SetGlobal(name) => {
+                let name = frame.function.read_constant(*name).to_string();
+                let value = self.stack.peek()?.clone();
+                match self.globals.get_mut(&name) {
+                    Some(global) => *global = value,
+                    None => return Err(VM::undefined_variable(&name)),
+                }
+            }
+            OpCode::GetLocal(slot) => {
+                let value = self.stack.get(*slot)?.clone();
+                self.stack.push(value)?;
+            }
+            OpCode::SetLocal(slot) => {
+                let value = self.stack.peek()?.clone();
+                self.stack.set(*slot, value)?;
+            }
+            OpCode::Import(path) => {
+                let path = frame.function.read_constant(*path).to_string();
+                let module = self.import(&path)?;
+                self.stack.push(module)?;
+            }
+            OpCode::ImportName(name) => {
+                let name = frame.function.read_constant(*name);
+                let module = match self.stack.pop()? {
+                    Value::Map(module) => module,
+                    value => {
+                        return Err(RuntimeError::new(&format!(
+                            "Can't import '{name}' from {} '{value}'.",
+                            value.type_name()
+                        )))
+                    }
+                };
+                let value = module.borrow().get(name)?.cloned();
+                match value {
+                    Some(value) => self.stack.push(value)?,
+                    None => {
+                        return Err(RuntimeError::new(&format!(
+                            "Module doesn't define '{name}'."
+                        )))
                     }
-                    v => Err(RuntimeError::new(&format!("Can't add operand {:?}", v)))?,
-                },
-                OpCode::Subtract => VM::binary(&mut self.stack, |a, b| Value::Number(a - b))?,
-                OpCode::Multiply => VM::binary(&mut self.stack, |a, b| Value::Number(a * b))?,
-                OpCode::Divide => VM::binary(&mut self.stack, |a, b| Value::Number(a / b))?,
-                OpCode::Not => {
-                    let old = self.stack.pop()?;
-                    let new = old.is_falsey();
-                    self.stack.push(Value::Boolean(new));
                 }
             }
+            OpCode::Jump(target) => frame.ip = *target,
+            OpCode::Throw => return Err(RuntimeError::Thrown(self.stack.pop()?)),
+            OpCode::JumpIfFalse(target) => {
+                if self.stack.peek()?.is_falsey() {
+                    frame.ip = *target;
+                }
+            }
+            OpCode::ForIter(slot, exit) => {
+                let cursor = match self.stack.get(slot + 1)? {
+                    Value::Integer(cursor) => *cursor as usize,
+                    v => panic!("Expected an iteration cursor but found {:?}", v),
+                };
+                match builtins::iterate(self.stack.get(*slot)?, cursor)? {
+                    Some((element, next)) => {
+                        self.stack.set(slot + 1, Value::Integer(next as i64))?;
+                        self.stack.push(element)?;
+                    }
+                    None => frame.ip = *exit,
+                }
+            }
+            OpCode::Not => {
+                let old = self.stack.pop()?;
+                let new = old.is_falsey();
+                self.stack.push(Value::Boolean(new))?;
+            }
+            OpCode::Equal => {
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                self.stack.push(Value::Boolean(a == b))?;
+            }
+            OpCode::Greater | OpCode::GreaterEqual | OpCode::Less | OpCode::LessEqual => {
+                let b = self.stack.pop()?;
+                let a = self.stack.pop()?;
+                // Unordered operands, like NaN, fail every comparison.
+                let ordering = compare(&a, &b)?;
+                let result = match op {
+                    OpCode::Greater => ordering == Some(Ordering::Greater),
+                    OpCode::GreaterEqual => ordering.is_some_and(Ordering::is_ge),
+                    OpCode::Less => ordering == Some(Ordering::Less),
+                    _ => ordering.is_some_and(Ordering::is_le),
+                };
+                self.stack.push(Value::Boolean(result))?;
+            }
+            OpCode::InRange(inclusive) => {
+                let range = self.stack.pop_many(3)?;
+                let (value, start, end) = (&range[0], &range[1], &range[2]);
+                // Only numbers can be in a range, anything else just isn't.
+                let contains = value.as_float().is_some()
+                    && matches!(
+                        compare(value, start)?,
+                        Some(Ordering::Greater | Ordering::Equal)
+                    )
+                    && match compare(value, end)? {
+                        Some(Ordering::Less) => true,
+                        Some(Ordering::Equal) => *inclusive,
+                        _ => false,
+                    };
+                self.stack.push(Value::Boolean(contains))?;
+            }
         }
+        Ok(None)
     }
 
-    fn binary<T>(stack: &mut Stack, implementation: T) -> InterpretResult<()>
-    where
-        T: Fn(f64, f64) -> Value,
-    {
-        let b = stack.pop_number()?;
-        let a = stack.pop_number()?;
-        let res = implementation(a, b);
-        stack.push(res);
+    /// Hands `error`, raised by the op before `frame.ip`, to the innermost
+    /// handler around that op, or gives it back if there is none.
+    fn catch(&mut self, frame: &mut CallFrame, error: RuntimeError) -> InterpretResult<()> {
+        let ip = frame.ip - 1;
+        let handler = match (&error, frame.function.handler_at(ip)) {
+            (RuntimeError::NoMoreOperations(_), _) => return Err(error),
+            (_, Some(handler)) => handler,
+            (_, None) => return Err(self.locate(error, frame.function.line(ip))),
+        };
+        // Errors from an imported module were located in it already.
+        let error = match error {
+            RuntimeError::Located { error, .. } => *error,
+            error => error,
+        };
+        let value = match error {
+            RuntimeError::Thrown(value) => value,
+            error => {
+                self.stats.allocations += 1;
+                error_object(&error.to_string(), frame.function.line(ip))
+            }
+        };
+        self.stack.truncate(handler.depth);
+        self.stack.push(value)?;
+        frame.ip = handler.target;
         Ok(())
     }
+
+    /// Records where an uncaught error happened, unless it escaped an
+    /// imported module that already did.
+    fn locate(&self, error: RuntimeError, line: Option<u32>) -> RuntimeError {
+        match (error, line) {
+            (error @ RuntimeError::Located { .. }, _) | (error, None) => error,
+            (error, Some(line)) => RuntimeError::Located {
+                error: Box::new(error),
+                file: self.path.clone(),
+                line,
+            },
+        }
+    }
+
+    fn trace(sink: &mut Box<dyn Write>, stack: &Stack, function: &Chunk, ip: usize) {
+        let values: String = stack
+            .contents()
+            .iter()
+            .map(|value| format!("[ {value} ]"))
+            .collect();
+        let line = function
+            .line(ip)
+            .map_or(String::from("?"), |line| line.to_string());
+
+        // A broken trace sink shouldn't take the program down with it.
+        let _ = writeln!(sink, "          {values}");
+        let _ = writeln!(
+            sink,
+            "{:04} {:>4} {}",
+            ip,
+            line,
+            function.code()[ip].describe(function)
+        );
+    }
+
+    /// The module at `path`, relative to the importing module's file. The
+    /// first import of a module runs it with globals and a stack of its own,
+    /// and the module is then a map of the globals it defined.
+    fn import(&mut self, path: &str) -> InterpretResult<Value> {
+        let importer = self.path.as_deref().and_then(Path::parent);
+        let file = importer.unwrap_or(Path::new("")).join(path);
+        let file = fs::canonicalize(&file)
+            .map_err(|error| RuntimeError::new(&format!("Can't open module '{path}': {error}.")))?;
+        if let Some(module) = self.modules.get(&file) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self.importing.iter().position(|module| *module == file) {
+            let cycle: Vec<String> = self.importing[start..]
+                .iter()
+                .chain([&file])
+                .map(|module| module.display().to_string())
+                .collect();
+            return Err(RuntimeError::new(&format!(
+                "Import cycle: {}.",
+                cycle.join(" -> ")
+            )));
+        }
+
+        let source = fs::read_to_string(&file)
+            .map_err(|error| RuntimeError::new(&format!("Can't read module '{path}': {error}.")))?;
+        let mut compiler = Compiler::from_source(&source);
+        compiler.optimize = self.optimize;
+        compiler.file = Some(file.clone());
+        let chunk = compiler.compile();
+        if compiler.had_error {
+            return Err(RuntimeError::new(&format!(
+                "Can't compile module '{path}'."
+            )));
+        }
+
+        let globals = mem::take(&mut self.globals);
+        let limit = self.stack.limit();
+        let stack = mem::replace(&mut self.stack, Stack::with_limit(limit));
+        let importer = self.path.replace(file.clone());
+        self.importing.push(file.clone());
+        let result = self.run(&chunk);
+        self.importing.pop();
+        self.path = importer;
+        self.stack = stack;
+        let mut exports: Vec<_> = mem::replace(&mut self.globals, globals)
+            .into_iter()
+            .collect();
+        result?;
+
+        exports.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut module = MapObject::default();
+        for (name, value) in exports {
+            module.insert(
+                Value::String(Rc::from(StringObject::from_owned(name))),
+                value,
+            )?;
+        }
+        self.stats.allocations += 1;
+        let module = Value::Map(Rc::new(RefCell::new(module)));
+        self.modules.insert(file, module.clone());
+        Ok(module)
+    }
+
+    fn undefined_variable(name: &str) -> RuntimeError {
+        RuntimeError::new(&format!("Undefined variable '{name}'."))
+    }
+
+    fn add(&mut self) -> InterpretResult<()> {
+        let b = self.stack.pop()?;
+        let a = self.stack.pop()?;
+        let sum = match (&a, &b) {
+            (Value::String(a), Value::String(b)) => {
+                self.stats.allocations += 1;
+                Value::String(Rc::from(StringObject::from_owned(format!(
+                    "{}{}",
+                    a.value, b.value
+                ))))
+            }
+            (a, b) if a.as_float().is_some() && b.as_float().is_some() => {
+                arithmetic(&OpCode::Add, a, b)?
+            }
+            (a, b) => {
+                return Err(RuntimeError::new(&format!(
+                    "Operands must be two numbers or two strings but found '{a}' and '{b}'."
+                )))
+            }
+        };
+        self.stack.push(sum)
+    }
+
+    fn binary(stack: &mut Stack, op: &OpCode) -> InterpretResult<()> {
+        let b = stack.pop()?;
+        let a = stack.pop()?;
+        stack.push(arithmetic(op, &a, &b)?)
+    }
+}
+
+/// Applies the arithmetic or bitwise `op` to `a` and `b`.
+///
+/// Two integers give an integer, and overflowing, dividing by zero or
+/// shifting by more than 63 bits is an error. If either operand is a float
+/// both are treated as floats, and so are integers raised to a negative
+/// power. Bitwise ops only take integers.
+pub fn arithmetic(op: &OpCode, a: &Value, b: &Value) -> InterpretResult<Value> {
+    match (op, a, b) {
+        (OpCode::Power, Value::Integer(_), Value::Integer(b)) if *b < 0 => (),
+        (op, Value::Integer(a), Value::Integer(b)) => {
+            return integer_arithmetic(op, *a, *b).map(Value::Integer)
+        }
+        _ => (),
+    }
+    let (Some(x), Some(y)) = (a.as_float(), b.as_float()) else {
+        return Err(RuntimeError::new(&format!(
+            "Operands must be numbers but found '{a}' and '{b}'."
+        )));
+    };
+    match op {
+        OpCode::Add => Ok(Value::Number(x + y)),
+        OpCode::Subtract => Ok(Value::Number(x - y)),
+        OpCode::Multiply => Ok(Value::Number(x * y)),
+        OpCode::Divide => Ok(Value::Number(x / y)),
+        OpCode::Modulo => Ok(Value::Number(x % y)),
+        OpCode::Power => Ok(Value::Number(x.powf(y))),
+        _ => Err(RuntimeError::new(&format!(
+            "Operands must be integers but found '{a}' and '{b}'."
+        ))),
+    }
+}
+
+/// What a runtime error is caught as: a map with its `message` and a `trace`
+/// of where it happened, innermost first.
+fn error_object(message: &str, line: Option<u32>) -> Value {
+    let string = |text: String| Value::String(Rc::from(StringObject::from_owned(text)));
+    let line = line.map_or(String::from("?"), |line| line.to_string());
+    let trace = ListObject::new(vec![string(format!("[line {line}] in script"))]);
+    let mut error = MapObject::default();
+    for (key, value) in [
+        ("message", string(message.to_string())),
+        ("trace", Value::List(Rc::new(RefCell::new(trace)))),
+    ] {
+        error
+            .insert(string(key.to_string()), value)
+            .expect("Strings are valid map keys");
+    }
+    Value::Map(Rc::new(RefCell::new(error)))
+}
+
+/// The message of an error object, or of anything else shaped like one.
+fn error_message(value: &Value) -> Option<String> {
+    let Value::Map(map) = value else {
+        return None;
+    };
+    let map = map.borrow();
+    map.entries
+        .iter()
+        .find(|(key, _)| matches!(key, Value::String(key) if key.value == "message"))
+        .map(|(_, message)| message.to_string())
+}
+
+/// Orders two numbers, comparing integers exactly rather than as floats.
+/// Nothing is ordered against NaN.
+fn compare(a: &Value, b: &Value) -> InterpretResult<Option<Ordering>> {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Some(a.cmp(b))),
+        _ => match (a.as_float(), b.as_float()) {
+            (Some(x), Some(y)) => Ok(x.partial_cmp(&y)),
+            _ => Err(RuntimeError::new(&format!(
+                "Operands must be numbers but found '{a}' and '{b}'."
+            ))),
+        },
+    }
+}
+
+/// Integer division and modulo truncate towards zero, so `-7 / 2` is `-3` and
+/// `-7 % 2` is `-1`.
+fn integer_arithmetic(op: &OpCode, a: i64, b: i64) -> InterpretResult<i64> {
+    let result = match op {
+        OpCode::Add => a.checked_add(b),
+        OpCode::Subtract => a.checked_sub(b),
+        OpCode::Multiply => a.checked_mul(b),
+        OpCode::Divide | OpCode::Modulo if b == 0 => {
+            return Err(RuntimeError::new("Division by zero."))
+        }
+        OpCode::Divide => a.checked_div(b),
+        OpCode::Modulo => a.checked_rem(b),
+        OpCode::Power => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        OpCode::BitAnd => Some(a & b),
+        OpCode::BitOr => Some(a | b),
+        OpCode::BitXor => Some(a ^ b),
+        OpCode::ShiftLeft | OpCode::ShiftRight if !(0..64).contains(&b) => {
+            return Err(RuntimeError::new(&format!("Can't shift by {b} bits.")))
+        }
+        OpCode::ShiftLeft => Some(a << b),
+        OpCode::ShiftRight => Some(a >> b),
+        op => panic!("Expected an arithmetic op but found {:?}", op),
+    };
+    result.ok_or_else(|| RuntimeError::new("Integer overflow."))
+}
+
+pub fn negate(value: &Value) -> InterpretResult<Value> {
+    match value {
+        Value::Number(n) => Ok(Value::Number(-n)),
+        Value::Integer(n) => n
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| RuntimeError::new("Integer overflow.")),
+        v => Err(RuntimeError::new(&format!(
+            "Operand must be a number but found '{v}'."
+        ))),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{CallFrame, VM};
+    use super::{CallFrame, Tracer, VM};
     use crate::{
         chunk::{Chunk, OpCode},
+        compiler::Compiler,
+        interpreter::{Interpreter, Options},
+        objects::StringObject,
+        protocol::SharedBuffer,
         value::Value,
         vm::RuntimeError,
     };
+    use std::{env, fs, process, rc::Rc};
 
     #[test]
     fn constants() {
@@ -151,6 +712,578 @@ mod tests {
         )
     }
 
+    #[test]
+    fn superinstructions() {
+        let mut chunk = Chunk::new();
+        chunk.emit_many(&mut vec![
+            OpCode::Constant(0),
+            OpCode::AddConstant(1),
+            OpCode::MultiplyConstant(1),
+        ]);
+        chunk.add_constant(Value::Number(2.0));
+        chunk.add_constant(Value::Number(3.0));
+
+        let mut function = CallFrame::new(&chunk);
+        assert_stack(&mut function, vec![Value::Number(15.0)])
+    }
+
+    #[test]
+    fn local_superinstructions() {
+        let mut chunk = Chunk::new();
+        chunk.emit_many(&mut vec![
+            OpCode::Constant(0),
+            OpCode::Constant(1),
+            OpCode::AddLocalConstant(0, 1),
+            OpCode::LessLocalsJumpIfFalse(0, 1, 5),
+            OpCode::Constant(0),
+            OpCode::LessLocalsJumpIfFalse(1, 0, 7),
+            OpCode::Constant(0),
+        ]);
+        chunk.add_constant(Value::Integer(1));
+        chunk.add_constant(Value::Integer(2));
+
+        let mut function = CallFrame::new(&chunk);
+        assert_stack(
+            &mut function,
+            vec![
+                Value::Integer(3),
+                Value::Integer(2),
+                Value::Integer(3),
+                Value::Boolean(false),
+                Value::Boolean(true),
+                Value::Integer(1),
+            ],
+        )
+    }
+
+    #[test]
+    fn integers() {
+        assert!(matches!(run("7 / 2"), Ok(Value::Integer(3))));
+        assert!(matches!(run("-7 % 2"), Ok(Value::Integer(-1))));
+        assert!(matches!(run("7 / 2.0"), Ok(Value::Number(n)) if n == 3.5));
+        assert!(matches!(run("7.5 % 2"), Ok(Value::Number(n)) if n == 1.5));
+        assert!(matches!(
+            run("0x7FFF_FFFF_FFFF_FFFF - 1"),
+            Ok(Value::Integer(n)) if n == i64::MAX - 1
+        ));
+        assert!(matches!(run("6 & 3 | 8 ^ 1"), Ok(Value::Integer(11))));
+        assert!(matches!(run("1 << 3 + 1 >> 1"), Ok(Value::Integer(8))));
+        assert!(matches!(run("-16 >> 2"), Ok(Value::Integer(-4))));
+        assert!(matches!(run("2 ** 3 ** 2"), Ok(Value::Integer(512))));
+        assert!(matches!(run("-2 ** 2"), Ok(Value::Integer(-4))));
+        assert!(matches!(run("2 ** -1"), Ok(Value::Number(n)) if n == 0.5));
+        assert!(matches!(run("4 ** 0.5 * 3"), Ok(Value::Number(n)) if n == 6.0));
+    }
+
+    #[test]
+    fn integer_errors() {
+        let error = |source| run(source).unwrap_err().to_string();
+        assert_eq!(error("0x7FFF_FFFF_FFFF_FFFF + 1"), "Integer overflow.");
+        assert_eq!(error("-0x7FFF_FFFF_FFFF_FFFF - 2"), "Integer overflow.");
+        assert_eq!(error("1 % 0"), "Division by zero.");
+        assert_eq!(error("1 << 64"), "Can't shift by 64 bits.");
+        assert_eq!(error("2 ** 63"), "Integer overflow.");
+        assert_eq!(
+            error("1.0 & 1"),
+            "Operands must be integers but found '1' and '1'."
+        );
+        assert_eq!(
+            error("1 - \"a\""),
+            "Operands must be numbers but found '1' and 'a'."
+        );
+
+        // The literal is negated after it is read, and i64::MAX is the
+        // largest one there is.
+        assert_eq!(
+            compile_error("-9223372036854775808"),
+            "Number literal '9223372036854775808' is too large."
+        );
+        assert!(matches!(
+            run("-9223372036854775807 - 1"),
+            Ok(Value::Integer(i64::MIN))
+        ));
+    }
+
+    #[test]
+    fn add_errors() {
+        let error = |source| run(source).unwrap_err().to_string();
+        assert_eq!(
+            error("1 + \"a\""),
+            "Operands must be two numbers or two strings but found '1' and 'a'."
+        );
+        assert_eq!(
+            error("\"a\" + 1"),
+            "Operands must be two numbers or two strings but found 'a' and '1'."
+        );
+        assert_eq!(
+            error("[1] + nil"),
+            "Operands must be two numbers or two strings but found '[1]' and 'nil'."
+        );
+        assert_eq!(run("\"a\" + \"b\"").unwrap().to_string(), "ab");
+    }
+
+    #[test]
+    fn lists() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(
+            value("[1, \"a\", [nil, 2.5], []]"),
+            "[1, \"a\", [nil, 2.5], []]"
+        );
+        assert_eq!(value("[1, 2, 3][0] + [1, 2, 3][-1]"), "4");
+        assert_eq!(value("[[1, 2], [3]][0][1]"), "2");
+        assert_eq!(value("[1, 2][1] = 5"), "5");
+        assert_eq!(value("[1, 2][-1] += 5"), "7");
+        assert_eq!(value("[1].push(2).push(3)"), "[1, 2, 3]");
+        assert_eq!(value("[1, 2].pop()"), "2");
+        assert_eq!(value("[1, 2, 3].len()"), "3");
+        assert_eq!(value("[1, 3].insert(1, 2).insert(-3, 0)"), "[0, 1, 2, 3]");
+        assert_eq!(value("[1, 2, 3].remove(-2)"), "2");
+        assert_eq!(value("[1, 2, 3, 4].slice(1, -1)"), "[2, 3]");
+        assert_eq!(value("[1, 2].slice(-5)"), "[1, 2]");
+        assert_eq!(value("[3, 1.5, 2].sort()"), "[1.5, 2, 3]");
+        assert_eq!(value("[\"b\", \"a\"].sort()"), "[\"a\", \"b\"]");
+    }
+
+    #[test]
+    fn self_containing_lists() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(value("var l = [1]; l.push(l); l"), "[1, [...]]");
+        assert_eq!(
+            value("var l = []; var m = [l]; l.push(m); [l, \"${m}\"]"),
+            "[[[[...]]], \"[[[...]]]\"]"
+        );
+        // A list that shows up twice without containing itself is written
+        // out in full both times.
+        assert_eq!(value("var l = [1]; [l, l]"), "[[1], [1]]");
+
+        assert_eq!(
+            value("var l = []; l.push(l); var m = []; m.push(m); [l == m, l == l]"),
+            "[true, true]"
+        );
+        assert_eq!(
+            value("var l = [1]; l.push(l); var m = [2]; m.push(m); l == m"),
+            "false"
+        );
+        assert_eq!(
+            run("var l = []; l.push(l); 1 + l").unwrap_err().to_string(),
+            "Operands must be two numbers or two strings but found '1' and '[[...]]'."
+        );
+    }
+
+    #[test]
+    fn list_errors() {
+        let error = |source| run(source).unwrap_err().to_string();
+        assert_eq!(
+            error("[1][1]"),
+            "List index 1 is out of bounds for a list of length 1."
+        );
+        assert_eq!(
+            error("[1][-2] = 0"),
+            "List index -2 is out of bounds for a list of length 1."
+        );
+        assert_eq!(
+            error("[1][0.5]"),
+            "List indexes must be integers but found '0.5'."
+        );
+        assert_eq!(error("[].pop()"), "Can't pop from an empty list.");
+        assert_eq!(error("[].push()"), "'push' takes 1 argument but got 0.");
+        assert_eq!(error("[].shuffle()"), "Undefined method 'shuffle' on list.");
+        assert_eq!(error("nil.len()"), "Undefined method 'len' on nil.");
+        assert_eq!(error("true[0]"), "Can't index into boolean 'true'.");
+        assert_eq!(
+            error("[1, \"a\"].sort()"),
+            "Only lists of numbers or of strings can be sorted."
+        );
+    }
+
+    #[test]
+    fn maps() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(
+            value("({\"b\": 1, 2: [nil], \"b\": true, })"),
+            "{\"b\": true, 2: [nil]}"
+        );
+        assert_eq!(value("({})"), "{}");
+        assert_eq!(value("({1: \"one\"})[1.0]"), "one");
+        assert_eq!(value("({\"a\": {\"b\": 2}})[\"a\"][\"b\"]"), "2");
+        assert_eq!(value("({\"a\": 1})[\"b\"] = 2"), "2");
+        assert_eq!(value("({\"a\": 1})[\"a\"] += 2"), "3");
+        assert_eq!(
+            value("({1: 2, nil: 3, false: 4}).keys()"),
+            "[1, nil, false]"
+        );
+        assert_eq!(value("({1: 2, 3: 4}).values()"), "[2, 4]");
+        assert_eq!(value("({-0.0: 1}).has(0)"), "true");
+        assert_eq!(value("({1: 2}).has(2)"), "false");
+        assert_eq!(value("({1: 2, 3: 4}).remove(1)"), "2");
+        assert_eq!(value("({1: 2, 3: 4}).len()"), "2");
+        assert_eq!(value("\"${ {\"a\": 1} }\""), "{\"a\": 1}");
+    }
+
+    #[test]
+    fn map_errors() {
+        let error = |source| run(source).unwrap_err().to_string();
+        assert_eq!(error("({\"a\": 1})[\"b\"]"), "Undefined key 'b'.");
+        assert_eq!(error("({}).remove(1)"), "Undefined key '1'.");
+        assert_eq!(error("({[1]: 2})"), "Can't use list '[1]' as a map key.");
+        assert_eq!(error("({}).has({})"), "Can't use map '{}' as a map key.");
+        assert_eq!(error("({}).get(1)"), "Undefined method 'get' on map.");
+    }
+
+    #[test]
+    fn self_containing_maps() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(
+            value("var m = {\"a\": 1}; m[\"self\"] = m; m"),
+            "{\"a\": 1, \"self\": {...}}"
+        );
+        assert_eq!(
+            value("var m = {}; var l = [m]; m[\"l\"] = l; l"),
+            "[{\"l\": [...]}]"
+        );
+        assert_eq!(
+            value("var m = {}; m[1] = m; var n = {}; n[1] = n; m == n"),
+            "true"
+        );
+        assert_eq!(
+            value("var m = {}; m[1] = m; var n = {}; n[2] = n; m == n"),
+            "false"
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(value("var a; a"), "nil");
+        assert_eq!(value("var a = 1; a = a + 2; a *= 3; a"), "9");
+        assert_eq!(
+            value("var a = 1; { var c = a + 1; { var b = c; a = b * 10; } } a"),
+            "20"
+        );
+        assert_eq!(value("var a = 1; { var b = 2; a = b += 1; } a"), "3");
+        assert_eq!(value("1; 2;"), "nil");
+        assert_eq!(run("a").unwrap_err(), "Undefined variable 'a'.");
+        assert_eq!(run("a = 1").unwrap_err(), "Undefined variable 'a'.");
+        assert_eq!(
+            run("{ var a = 1; var a = 2; }").unwrap_err(),
+            "Compilation failed."
+        );
+        assert_eq!(run("{ var a = a; }").unwrap_err(), "Compilation failed.");
+    }
+
+    #[test]
+    fn for_in() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(
+            value("var out = []; for (x in [1, 2, 3]) out.push(x * 2); out"),
+            "[2, 4, 6]"
+        );
+        assert_eq!(
+            value("var out = \"\"; for (k in {\"a\": 1, \"b\": 2}) out += k; out"),
+            "ab"
+        );
+        assert_eq!(
+            value("var out = []; for (c in \"h\u{e9}!\") { var d = c; out.push(d); } out"),
+            "[\"h\", \"\u{e9}\", \"!\"]"
+        );
+        assert_eq!(
+            value("var t = 0; for (x in [1, 2]) for (y in [10, 20]) t += x * y; t"),
+            "90"
+        );
+        assert_eq!(value("var l = [1, 2, 3]; for (x in l) l.pop(); l"), "[1]");
+        assert_eq!(
+            run("for (x in 3) x;").unwrap_err(),
+            "Can't iterate over integer '3'."
+        );
+    }
+
+    #[test]
+    fn loop_control() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(
+            value("var out = []; for (x in [1, 2]) { var y = x; { out.push(y); break; } } out"),
+            "[1]"
+        );
+        assert_eq!(
+            value("var out = []; for (x in [1, 2]) { out.push(x); continue; out.push(0); } out"),
+            "[1, 2]"
+        );
+        assert_eq!(
+            value(
+                "var out = []; for (x in [1, 2]) { for (y in \"ab\") { out.push(y); break; } \
+                 out.push(x); } out"
+            ),
+            "[\"a\", 1, \"a\", 2]"
+        );
+        assert_eq!(
+            value("var a = 1; for (x in [2]) { var b = 3; break; } a"),
+            "1"
+        );
+
+        for (source, message) in [
+            ("break;", "Can't use 'break' outside of a loop."),
+            ("{ continue; }", "Can't use 'continue' outside of a loop."),
+        ] {
+            assert_eq!(compile_error(source), message);
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(
+            value("[1 < 2, 2 <= 2, 3 > 4, 3 >= 3.5, 1 == 1.0, \"a\" != \"a\"]"),
+            "[true, true, false, false, true, false]"
+        );
+        assert_eq!(
+            value("[nil or \"x\", 0 or 1, false and 1, 1 and 2]"),
+            "[\"x\", 0, false, 2]"
+        );
+        assert_eq!(
+            value("var a = 0; true or (a = 1); false and (a = 2); a"),
+            "0"
+        );
+        assert_eq!(
+            value(
+                "var nan = 0.0 / 0.0; [nan < 1, nan <= 1, nan > 1, nan >= 1, 1 >= nan, nan <= nan]"
+            ),
+            "[false, false, false, false, false, false]"
+        );
+        assert_eq!(
+            value("[2 >= 2, 2 <= 1.5, 1 <= 2, -1 >= 0]"),
+            "[true, false, true, false]"
+        );
+        assert_eq!(
+            run("\"a\" < 1").unwrap_err(),
+            "Operands must be numbers but found 'a' and '1'."
+        );
+    }
+
+    #[test]
+    fn match_statement() {
+        let source = "\
+var out = [];
+for (x in [1, 2, 4, 6, 7.5, 8, -3, \"a\", nil, 10]) {
+  match (x) {
+    1 | 2 => out.push(\"small\");
+    3..5 => out.push(\"mid\");
+    5..=8 if x != 6 => out.push(\"upper\");
+    -10..0 => out.push(\"negative\");
+    \"a\" => { var s = \"letter\"; out.push(s); }
+    nil => continue;
+    _ => out.push(\"other\");
+  }
+}
+out";
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[\"small\", \"small\", \"mid\", \"other\", \"upper\", \"upper\", \"negative\", \
+             \"letter\", \"other\"]"
+        );
+        assert_eq!(run("match (1) { 2 => 3; } 4").unwrap().to_string(), "4");
+
+        for (source, message) in [
+            ("match (1) { _ => 1; 2 => 2; }", "Unreachable match arm."),
+            (
+                "match (1) { 0..3 | 9 => 1; 1 | 2..=2 => 2; }",
+                "Unreachable match arm.",
+            ),
+            ("match (1) { x => 1; }", "Expect pattern."),
+            (
+                "match (1) { \"a\"..2 => 1; }",
+                "Range bounds must be numbers.",
+            ),
+        ] {
+            assert_eq!(compile_error(source), message);
+        }
+    }
+
+    #[test]
+    fn exceptions() {
+        let value = |source| run(source).unwrap().to_string();
+        assert_eq!(
+            value("var r = []; try { r.push(1); throw \"boom\"; r.push(2); } catch (e) { r.push(e); } r"),
+            "[1, \"boom\"]"
+        );
+        assert_eq!(
+            value("var r; try { var a = 1; [a].pop(); missing; } catch (e) { r = e; } r"),
+            "{\"message\": \"Undefined variable 'missing'.\", \"trace\": [\"[line 1] in script\"]}"
+        );
+        assert_eq!(
+            value("var r = []; try { r.push(1); } finally { r.push(2); } r"),
+            "[1, 2]"
+        );
+        assert_eq!(
+            value(
+                "var r = []; try { try { throw 1; } catch (e) { throw e + 1; } \
+                 finally { r.push(\"f\"); } } catch (e) { r.push(e); } r"
+            ),
+            "[\"f\", 2]"
+        );
+        assert_eq!(
+            value("var r = []; for (x in [1, 2, 3]) { try { 1 / (x - 2); } catch (e) { r.push(x); break; } } r"),
+            "[2]"
+        );
+        assert_eq!(run("throw \"x\";").unwrap_err(), "Uncaught exception: x");
+        assert_eq!(
+            run("try { [].pop(); } finally { 1; }").unwrap_err(),
+            "Can't pop from an empty list."
+        );
+
+        for (source, message) in [
+            ("try { 1; }", "Expect 'catch' or 'finally' after try block."),
+            (
+                "for (x in [1]) { try { break; } finally { 1; } }",
+                "Can't jump out of a 'try' that has a 'finally'.",
+            ),
+        ] {
+            assert_eq!(compile_error(source), message);
+        }
+    }
+
+    #[test]
+    fn modules() {
+        let root = env::temp_dir().join(format!("rux-modules-{}", process::id()));
+        fs::create_dir_all(root.join("lib")).unwrap();
+        for (file, source) in [
+            (
+                "lib/util.lox",
+                "from \"consts.lox\" import base; var scale = base * 10;",
+            ),
+            ("lib/consts.lox", "var base = 4; var count = 0;"),
+            ("lib/broken.lox", "var a = 1;\na + nil;"),
+            ("a.lox", "import \"b.lox\" as b;"),
+            ("b.lox", "import \"a.lox\" as a;"),
+        ] {
+            fs::write(root.join(file), source).unwrap();
+        }
+        let run_in = |source: &str| {
+            let main = root.join("main.lox");
+            fs::write(&main, source).unwrap();
+            let mut interpreter = Interpreter::with_options(Options::default());
+            interpreter.set_script_path(&main);
+            interpreter
+                .interpret(source)
+                .map_err(|error| error.message())
+        };
+
+        assert_eq!(
+            run_in(
+                "import \"lib/util.lox\" as util; from \"lib/consts.lox\" import count; \
+                 var base = 1; [util, count, base]"
+            )
+            .unwrap()
+            .to_string(),
+            "[{\"base\": 4, \"scale\": 40}, 0, 1]"
+        );
+        // Both imports get the same cached module.
+        assert_eq!(
+            run_in(
+                "import \"lib/consts.lox\" as a; import \"lib/consts.lox\" as b; \
+                 a[\"count\"] = 1; b[\"count\"]"
+            )
+            .unwrap()
+            .to_string(),
+            "1"
+        );
+        assert!(run_in("import \"a.lox\" as a;")
+            .unwrap_err()
+            .starts_with("Import cycle: "));
+        assert_eq!(
+            run_in("from \"lib/consts.lox\" import nope;").unwrap_err(),
+            "Module doesn't define 'nope'."
+        );
+        assert!(run_in("import \"missing.lox\" as m;")
+            .unwrap_err()
+            .starts_with("Can't open module 'missing.lox'"));
+
+        // Errors inside a module point at the module, not the import.
+        let main = root.join("main.lox");
+        let mut interpreter = Interpreter::with_options(Options::default());
+        interpreter.set_script_path(&main);
+        let error = interpreter
+            .interpret("import \"lib/broken.lox\" as m;")
+            .unwrap_err();
+        let module = fs::canonicalize(root.join("lib/broken.lox")).unwrap();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("[{}:2] Error: ", module.display())));
+
+        let mut chunk = Chunk::new();
+        let nil = chunk.add_constant(Value::Nil);
+        let name = chunk.add_constant(Value::String(Rc::new(StringObject::from_owned(
+            String::from("x"),
+        ))));
+        chunk.emit_many(&mut vec![OpCode::Constant(nil), OpCode::ImportName(name)]);
+        assert_eq!(
+            VM::new().run(&chunk).unwrap_err().to_string(),
+            "Can't import 'x' from nil 'nil'."
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn error_locations() {
+        let main = env::temp_dir().join(format!("rux-locations-{}.lox", process::id()));
+        let source = "var a = 1;\n\nb;";
+        fs::write(&main, source).unwrap();
+        let mut interpreter = Interpreter::with_options(Options::default());
+        interpreter.set_script_path(&main);
+        let error = interpreter.interpret(source).unwrap_err().to_string();
+        fs::remove_file(&main).unwrap();
+        assert_eq!(
+            error,
+            format!("[{}:3] Error: Undefined variable 'b'.", main.display())
+        );
+
+        let mut interpreter = Interpreter::with_options(Options::default());
+        let error = interpreter.interpret("1;\nthrow 2;").unwrap_err();
+        assert_eq!(error.to_string(), "[line 2] Error: Uncaught exception: 2");
+        assert_eq!(error.message(), "Uncaught exception: 2");
+    }
+
+    #[test]
+    fn trace() {
+        let mut chunk = Chunk::new();
+        let one = chunk.add_constant(Value::Number(1.0));
+        chunk.emit(OpCode::Constant(one), 1);
+        chunk.emit(OpCode::Negate, 2);
+        chunk.emit(OpCode::Return, 2);
+
+        let output = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.trace = Some(Tracer(Box::new(output.clone())));
+        vm.run(&chunk).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            "          \n\
+             0000    1 Constant         0 'Number(1.0)'\n\
+             \x20         [ 1 ]\n\
+             0001    2 Negate\n\
+             \x20         [ -1 ]\n\
+             0002    2 Return\n"
+        );
+    }
+
+    fn run(source: &str) -> Result<Value, String> {
+        Interpreter::with_options(Options::default())
+            .interpret(source)
+            .map_err(|error| error.message())
+    }
+
+    /// The first diagnostic compiling `source` reports. Only warnings let
+    /// the script compile.
+    fn compile_error(source: &str) -> String {
+        let mut compiler = Compiler::from_source(source);
+        compiler.print_errors = false;
+        compiler.compile();
+        let diagnostic = &compiler.diagnostics[0];
+        assert_eq!(compiler.had_error, !diagnostic.warning);
+        diagnostic.message.clone()
+    }
+
     fn assert_stack(function: &mut CallFrame, stack: Vec<Value>) {
         let mut vm = VM::new();
         match vm.run(function.function) {